@@ -0,0 +1,59 @@
+//! Benchmarks of iterating the points of views close to the boundary of the set
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use mandelbrot::render::support::{boundary_views, Grid};
+
+/// Number of points per row and column of the benchmarked views
+const SIZE: usize = 64;
+
+const MAX_ITERATIONS: u32 = 10_000;
+
+/// Grids of the views of the Mandelbrot set with many points inside the set and close to its
+/// boundary, with or without the periodicity check
+fn grids(periodicity_check: bool) -> Vec<(&'static str, Grid)> {
+    boundary_views()
+        .into_iter()
+        .filter(|(name, ..)| ["cusp", "neck", "minibrot"].contains(name))
+        .map(|(name, view, julia)| (name, Grid::new(&view, julia, SIZE, periodicity_check)))
+        .collect()
+}
+
+/// Iterating points one by one with and without stopping periodic orbits early
+fn periodicity(c: &mut Criterion) {
+    let mut group = c.benchmark_group("periodicity");
+    group.sample_size(10);
+
+    for (check, periodicity_check) in [("check", true), ("no check", false)] {
+        for (name, grid) in grids(periodicity_check) {
+            group.bench_function(BenchmarkId::new(check, name), |b| {
+                b.iter(|| grid.iterate(MAX_ITERATIONS))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Iterating points one by one, and in lanes without SIMD instructions and with every SIMD
+/// extension the CPU supports
+fn simd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    group.sample_size(10);
+
+    for (name, grid) in grids(true) {
+        group.bench_function(BenchmarkId::new("scalar", name), |b| {
+            b.iter(|| grid.iterate(MAX_ITERATIONS))
+        });
+        for kernel in grid.lane_kernels() {
+            group.bench_function(BenchmarkId::new(kernel, name), |b| {
+                b.iter(|| grid.iterate_lanes(kernel, MAX_ITERATIONS))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, periodicity, simd);
+criterion_main!(benches);
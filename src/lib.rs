@@ -0,0 +1,5 @@
+//! Rendering of the Mandelbrot set and the other fractals of the viewer
+//!
+//! This is a library of its own so that the benchmarks can use it.
+
+pub mod render;
@@ -1,4 +1,10 @@
-use gtk::{gio, prelude::*};
+use gtk::{gio, glib, prelude::*};
+
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use mandelbrot::render;
 
 mod widget;
 
@@ -10,7 +16,164 @@ fn make_application() -> gio::Application {
     application.connect_activate(|app| {
         let window = gtk::ApplicationWindow::new(app);
         let widget = widget::Widget::new();
-        window.set_child(Some(&widget));
+
+        // Julia set of the point that is picked in the Mandelbrot set, rendered with the
+        // same settings
+        let julia = widget::Widget::new();
+        julia.set_julia(true);
+        julia.set_visible(false);
+        for (source, target) in [("picked-x", "julia-x"), ("picked-y", "julia-y")] {
+            widget.bind_property(source, &julia, target).build();
+        }
+        for pspec in widget.list_properties() {
+            let name = pspec.name();
+            if pspec.owner_type() == widget::Widget::static_type()
+                && pspec.flags().contains(glib::ParamFlags::WRITABLE)
+                && !name.starts_with("julia")
+            {
+                widget
+                    .bind_property(name, &julia, name)
+                    .sync_create()
+                    .build();
+            }
+        }
+
+        let paned = gtk::Paned::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .start_child(&widget)
+            .end_child(&julia)
+            .build();
+        window.set_child(Some(&paned));
+
+        let header_bar = gtk::HeaderBar::new();
+
+        let fractal = enum_drop_down(&widget, "fractal");
+        fractal.set_tooltip_text(Some("Fractal"));
+        header_bar.pack_start(&fractal);
+
+        let exponent = gtk::SpinButton::with_range(1.1, 16.0, 0.1);
+        exponent.set_digits(2);
+        exponent.set_tooltip_text(Some("Exponent"));
+        widget
+            .bind_property("exponent", &exponent, "value")
+            .bidirectional()
+            .sync_create()
+            .build();
+        widget
+            .bind_property("fractal", &exponent, "visible")
+            .transform_to(|_, fractal: widget::FractalFamily| {
+                Some(fractal == widget::FractalFamily::Multibrot)
+            })
+            .sync_create()
+            .build();
+        header_bar.pack_start(&exponent);
+
+        let phoenix_p = gtk::SpinButton::with_range(-2.0, 2.0, 0.05);
+        phoenix_p.set_digits(3);
+        phoenix_p.set_tooltip_text(Some("Weight of the previous value of the orbit"));
+        widget
+            .bind_property("phoenix-p", &phoenix_p, "value")
+            .bidirectional()
+            .sync_create()
+            .build();
+        widget
+            .bind_property("fractal", &phoenix_p, "visible")
+            .transform_to(|_, fractal: widget::FractalFamily| {
+                Some(fractal == widget::FractalFamily::Phoenix)
+            })
+            .sync_create()
+            .build();
+        header_bar.pack_start(&phoenix_p);
+
+        let formula = gtk::MenuButton::builder()
+            .label("Formula")
+            .tooltip_text("Iteration and escape condition")
+            .popover(&formula_popover(&widget))
+            .build();
+        widget
+            .bind_property("fractal", &formula, "visible")
+            .transform_to(|_, fractal: widget::FractalFamily| {
+                Some(fractal == widget::FractalFamily::Formula)
+            })
+            .sync_create()
+            .build();
+        header_bar.pack_start(&formula);
+
+        let polynomial = gtk::MenuButton::builder()
+            .label("Polynomial")
+            .tooltip_text("Polynomial whose roots Newton's method finds")
+            .popover(&polynomial_popover(&widget))
+            .build();
+        widget
+            .bind_property("fractal", &polynomial, "visible")
+            .transform_to(|_, fractal: widget::FractalFamily| {
+                Some(fractal == widget::FractalFamily::Newton)
+            })
+            .sync_create()
+            .build();
+        header_bar.pack_start(&polynomial);
+
+        let render_mode = enum_drop_down(&widget, "render-mode");
+        render_mode.set_tooltip_text(Some("What is shown for every pixel"));
+        header_bar.pack_start(&render_mode);
+
+        let max_iterations =
+            gtk::SpinButton::with_range(1.0, widget::MAX_MAX_ITERATIONS as f64, 100.0);
+        max_iterations.set_tooltip_text(Some("Maximum iterations"));
+        widget
+            .bind_property("max-iterations", &max_iterations, "value")
+            .bidirectional()
+            .sync_create()
+            .build();
+        header_bar.pack_start(&max_iterations);
+
+        let iteration_mode = enum_drop_down(&widget, "iteration-mode");
+        iteration_mode.set_tooltip_text(Some("Iteration limit"));
+        header_bar.pack_start(&iteration_mode);
+
+        let effective_max_iterations = gtk::Label::new(None);
+        effective_max_iterations.add_css_class("dim-label");
+        effective_max_iterations.set_tooltip_text(Some("Iteration limit of the current image"));
+        widget
+            .bind_property(
+                "effective-max-iterations",
+                &effective_max_iterations,
+                "label",
+            )
+            .transform_to(|_, max_iterations: u32| Some(format!("{max_iterations} iterations")))
+            .sync_create()
+            .build();
+        header_bar.pack_start(&effective_max_iterations);
+
+        let subdivision = gtk::ToggleButton::builder()
+            .icon_name("view-grid-symbolic")
+            .tooltip_text("Fill rectangles inside the set without iterating them")
+            .build();
+        widget
+            .bind_property("subdivision", &subdivision, "active")
+            .bidirectional()
+            .sync_create()
+            .build();
+        header_bar.pack_start(&subdivision);
+
+        let show_julia = gtk::ToggleButton::builder()
+            .label("Julia")
+            .tooltip_text("Show the Julia set of the point that is clicked, or hovered with Ctrl")
+            .build();
+        show_julia
+            .bind_property("active", &julia, "visible")
+            .sync_create()
+            .build();
+        header_bar.pack_start(&show_julia);
+
+        let coloring = gtk::MenuButton::builder()
+            .icon_name("applications-graphics-symbolic")
+            .tooltip_text("Coloring")
+            .popover(&coloring_popover(&window, &widget))
+            .build();
+        header_bar.pack_end(&coloring);
+
+        window.set_titlebar(Some(&header_bar));
 
         widget.grab_focus();
 
@@ -23,6 +186,557 @@ fn make_application() -> gio::Application {
     application.upcast()
 }
 
+/// Creates a popover for typing in the formula of `widget`
+///
+/// The formula is only applied while it is valid, otherwise the error is shown and underlined
+/// in the entry it was found in.
+fn formula_popover(widget: &widget::Widget) -> gtk::Popover {
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .build();
+
+    let current = widget.formula();
+    let iteration = gtk::Entry::builder()
+        .text(current.iteration())
+        .tooltip_text("Next value of the orbit from z and c, like z^3 + c*sin(z)")
+        .width_chars(30)
+        .build();
+    let bailout = gtk::Entry::builder()
+        .text(current.bailout())
+        .tooltip_text("Condition under which the orbit escaped, like abs(z) > 256")
+        .width_chars(30)
+        .build();
+    let error = gtk::Label::builder()
+        .xalign(0.0)
+        .wrap(true)
+        .css_classes(["error"])
+        .build();
+
+    for &(row, label, entry) in &[(0, "Formula", &iteration), (1, "Bailout", &bailout)] {
+        let label = gtk::Label::builder().label(label).xalign(0.0).build();
+        grid.attach(&label, 0, row, 1, 1);
+        grid.attach(entry, 1, row, 1, 1);
+    }
+    grid.attach(&error, 0, 2, 2, 1);
+
+    let apply = glib::clone!(
+        #[weak]
+        widget,
+        #[weak]
+        iteration,
+        #[weak]
+        bailout,
+        #[weak]
+        error,
+        move || {
+            for entry in [&iteration, &bailout] {
+                mark_error(entry, None);
+            }
+
+            match widget::Formula::new(&iteration.text(), &bailout.text()) {
+                Ok(formula) => {
+                    error.set_label("");
+                    widget.set_formula(formula);
+                }
+                Err(err) => {
+                    let entry = match err.part {
+                        widget::FormulaPart::Iteration => &iteration,
+                        widget::FormulaPart::Bailout => &bailout,
+                    };
+                    mark_error(entry, Some(err.error.span.clone()));
+                    error.set_label(&err.to_string());
+                }
+            }
+        }
+    );
+    for entry in [&iteration, &bailout] {
+        entry.connect_changed(glib::clone!(
+            #[strong]
+            apply,
+            move |_| apply()
+        ));
+    }
+
+    gtk::Popover::builder().child(&grid).build()
+}
+
+/// Creates a popover for typing in the polynomial of the Newton fractal of `widget`, either
+/// by its roots or by its coefficients
+///
+/// Like the formula, the polynomial is only applied while it is valid.
+fn polynomial_popover(widget: &widget::Widget) -> gtk::Popover {
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .build();
+
+    let current = widget.polynomial();
+    let input = gtk::DropDown::from_strings(&["Roots", "Coefficients"]);
+    input.set_selected(match current.input() {
+        widget::PolynomialInput::Roots => 0,
+        widget::PolynomialInput::Coefficients => 1,
+    });
+    let values = gtk::Entry::builder()
+        .text(current.source())
+        .tooltip_text(
+            "Comma-separated roots like 1, -0.5 + 0.866i, -0.5 - 0.866i, or coefficients \
+             starting with the highest power like 1, 0, 0, -1",
+        )
+        .width_chars(30)
+        .build();
+    let error = gtk::Label::builder()
+        .xalign(0.0)
+        .wrap(true)
+        .css_classes(["error"])
+        .build();
+
+    grid.attach(&input, 0, 0, 1, 1);
+    grid.attach(&values, 1, 0, 1, 1);
+    grid.attach(&error, 0, 1, 2, 1);
+
+    let apply = glib::clone!(
+        #[weak]
+        widget,
+        #[weak]
+        input,
+        #[weak]
+        values,
+        #[weak]
+        error,
+        move || {
+            mark_error(&values, None);
+
+            let input = match input.selected() {
+                0 => widget::PolynomialInput::Roots,
+                _ => widget::PolynomialInput::Coefficients,
+            };
+            match widget::Polynomial::new(input, &values.text()) {
+                Ok(polynomial) => {
+                    error.set_label("");
+                    widget.set_polynomial(polynomial);
+                }
+                Err(err) => {
+                    mark_error(&values, Some(err.span.clone()));
+                    error.set_label(&err.to_string());
+                }
+            }
+        }
+    );
+    values.connect_changed(glib::clone!(
+        #[strong]
+        apply,
+        move |_| apply()
+    ));
+    input.connect_selected_notify(move |_| apply());
+
+    gtk::Popover::builder().child(&grid).build()
+}
+
+/// Marks `entry` as containing an error and underlines the bytes in `span`, or clears that
+/// without a `span`
+fn mark_error(entry: &gtk::Entry, span: Option<Range<usize>>) {
+    let attributes = gtk::pango::AttrList::new();
+    match span {
+        Some(span) => {
+            entry.add_css_class("error");
+
+            let mut underline = gtk::pango::AttrInt::new_underline(gtk::pango::Underline::Error);
+            underline.set_start_index(span.start as u32);
+            underline.set_end_index(span.end as u32);
+            attributes.insert(underline);
+        }
+        None => entry.remove_css_class("error"),
+    }
+    entry.set_attributes(&attributes);
+}
+
+/// Creates a popover with the coloring settings of `widget`
+fn coloring_popover(window: &gtk::ApplicationWindow, widget: &widget::Widget) -> gtk::Popover {
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .build();
+    let mut row = 0;
+    let mut add_row = |label: &str, child: &gtk::Widget| {
+        let label = gtk::Label::builder().label(label).xalign(0.0).build();
+        grid.attach(&label, 0, row, 1, 1);
+        grid.attach(child, 1, row, 1, 1);
+        row += 1;
+    };
+
+    add_row("Palette", palette_drop_down(window, widget).upcast_ref());
+
+    let interpolation = enum_drop_down(widget, "interpolation");
+    interpolation.set_tooltip_text(Some("Color space in which the palette colors are blended"));
+    add_row("Blending", interpolation.upcast_ref());
+
+    let period = gtk::SpinButton::with_range(1.0, 100_000.0, 10.0);
+    widget
+        .bind_property("palette-period", &period, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    add_row("Period", period.upcast_ref());
+
+    let algorithm = enum_drop_down(widget, "coloring-algorithm");
+    algorithm.set_tooltip_text(Some("How escaped points are positioned in the palette"));
+    add_row("Algorithm", algorithm.upcast_ref());
+
+    let stripe_density = gtk::SpinButton::with_range(1.0, 100.0, 1.0);
+    stripe_density.set_tooltip_text(Some("Number of stripes per turn"));
+    widget
+        .bind_property("stripe-density", &stripe_density, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("coloring-algorithm", &stripe_density, "sensitive")
+        .transform_to(|_, algorithm: widget::ColoringAlgorithm| {
+            Some(algorithm == widget::ColoringAlgorithm::Stripe)
+        })
+        .sync_create()
+        .build();
+    add_row("Stripe density", stripe_density.upcast_ref());
+
+    for (label, property, tooltip) in [
+        ("Trap x", "trap-x", "Real part of the center of the trap"),
+        (
+            "Trap y",
+            "trap-y",
+            "Imaginary part of the center of the trap",
+        ),
+    ] {
+        let trap = gtk::SpinButton::with_range(-4.0, 4.0, 0.01);
+        trap.set_digits(3);
+        trap.set_tooltip_text(Some(tooltip));
+        widget
+            .bind_property(property, &trap, "value")
+            .bidirectional()
+            .sync_create()
+            .build();
+        widget
+            .bind_property("coloring-algorithm", &trap, "sensitive")
+            .transform_to(|_, algorithm: widget::ColoringAlgorithm| {
+                Some(matches!(
+                    algorithm,
+                    widget::ColoringAlgorithm::PointTrap
+                        | widget::ColoringAlgorithm::LineTrap
+                        | widget::ColoringAlgorithm::CrossTrap
+                ))
+            })
+            .sync_create()
+            .build();
+        add_row(label, trap.upcast_ref());
+    }
+
+    let trap_angle = gtk::SpinButton::with_range(0.0, 180.0, 15.0);
+    trap_angle.set_tooltip_text(Some("Angle of the line or cross in degrees"));
+    widget
+        .bind_property("trap-angle", &trap_angle, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("coloring-algorithm", &trap_angle, "sensitive")
+        .transform_to(|_, algorithm: widget::ColoringAlgorithm| {
+            Some(matches!(
+                algorithm,
+                widget::ColoringAlgorithm::LineTrap | widget::ColoringAlgorithm::CrossTrap
+            ))
+        })
+        .sync_create()
+        .build();
+    add_row("Trap angle", trap_angle.upcast_ref());
+
+    let interior = enum_drop_down(widget, "interior-coloring");
+    interior.set_tooltip_text(Some("How points inside the set are colored"));
+    add_row("Interior", interior.upcast_ref());
+
+    let mapping = enum_drop_down(widget, "color-mapping");
+    mapping.set_tooltip_text(Some("How iteration counts are mapped to the palette"));
+    add_row("Mapping", mapping.upcast_ref());
+
+    let histogram_blend = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, 1.0, 0.01);
+    histogram_blend.set_tooltip_text(Some(
+        "Weight of the histogram mapping against the smooth mapping",
+    ));
+    widget
+        .bind_property("histogram-blend", &histogram_blend.adjustment(), "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("color-mapping", &histogram_blend, "sensitive")
+        .transform_to(|_, mapping: widget::ColorMapping| {
+            Some(mapping == widget::ColorMapping::Histogram)
+        })
+        .sync_create()
+        .build();
+    add_row("Histogram", histogram_blend.upcast_ref());
+
+    let distance_estimation = enum_drop_down(widget, "distance-estimation");
+    distance_estimation.set_tooltip_text(Some(
+        "How the estimated distance to the boundary of the set is shown",
+    ));
+    add_row("Boundary", distance_estimation.upcast_ref());
+
+    let boundary_width = gtk::SpinButton::with_range(0.1, 100.0, 0.1);
+    boundary_width.set_tooltip_text(Some("Width of the boundary in pixels"));
+    widget
+        .bind_property("boundary-width", &boundary_width, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("distance-estimation", &boundary_width, "sensitive")
+        .transform_to(|_, distance_estimation: widget::DistanceEstimation| {
+            Some(distance_estimation != widget::DistanceEstimation::Off)
+        })
+        .sync_create()
+        .build();
+    add_row("Boundary width", boundary_width.upcast_ref());
+
+    let lighting = gtk::Switch::builder().halign(gtk::Align::Start).build();
+    widget
+        .bind_property("lighting", &lighting, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    add_row("Lighting", lighting.upcast_ref());
+
+    let light_angle = gtk::SpinButton::with_range(0.0, 360.0, 15.0);
+    light_angle.set_tooltip_text(Some("Direction of the light in degrees"));
+    widget
+        .bind_property("light-angle", &light_angle, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("lighting", &light_angle, "sensitive")
+        .sync_create()
+        .build();
+    add_row("Light angle", light_angle.upcast_ref());
+
+    let light_height = gtk::SpinButton::with_range(0.0, 10.0, 0.1);
+    light_height.set_tooltip_text(Some("Height of the light above the surface"));
+    widget
+        .bind_property("light-height", &light_height, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    widget
+        .bind_property("lighting", &light_height, "sensitive")
+        .sync_create()
+        .build();
+    add_row("Light height", light_height.upcast_ref());
+
+    let offset = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, 1.0, 0.01);
+    offset.set_width_request(150);
+    widget
+        .bind_property("color-offset", &offset.adjustment(), "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    add_row("Offset", offset.upcast_ref());
+
+    let reversed = gtk::Switch::builder().halign(gtk::Align::Start).build();
+    widget
+        .bind_property("palette-reversed", &reversed, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    add_row("Reversed", reversed.upcast_ref());
+
+    let load_palette = gtk::Button::builder()
+        .label("Load Palette…")
+        .action_name("win.load-palette")
+        .build();
+    grid.attach(&load_palette, 0, row, 2, 1);
+
+    gtk::Popover::builder().child(&grid).build()
+}
+
+/// Creates a drop-down for selecting the palette of `widget`
+///
+/// Additional palettes can be loaded from files with the `win.load-palette` action, which is
+/// added to `window`.
+fn palette_drop_down(window: &gtk::ApplicationWindow, widget: &widget::Widget) -> gtk::DropDown {
+    let palettes = Rc::new(RefCell::new(widget::Palette::builtin().to_vec()));
+    let names = palettes
+        .borrow()
+        .iter()
+        .map(|palette| palette.name().to_string())
+        .collect::<gtk::StringList>();
+    let drop_down = gtk::DropDown::new(Some(names.clone()), gtk::Expression::NONE);
+
+    // The list of palettes can't be captured by property binding transforms as it is not
+    // thread-safe, so keep the drop-down and the property in sync manually
+    let select_palette = {
+        let palettes = palettes.clone();
+        move |drop_down: &gtk::DropDown, palette: &widget::Palette| {
+            let position = palettes.borrow().iter().position(|p| p == palette);
+            if let Some(position) = position {
+                drop_down.set_selected(position as u32);
+            }
+        }
+    };
+    select_palette(&drop_down, &widget.palette());
+    widget.connect_palette_notify(glib::clone!(
+        #[weak]
+        drop_down,
+        #[strong]
+        select_palette,
+        move |widget| select_palette(&drop_down, &widget.palette())
+    ));
+    drop_down.connect_selected_notify(glib::clone!(
+        #[weak]
+        widget,
+        #[strong]
+        palettes,
+        move |drop_down| {
+            let palette = palettes
+                .borrow()
+                .get(drop_down.selected() as usize)
+                .cloned();
+            if let Some(palette) = palette {
+                widget.set_palette(palette);
+            }
+        }
+    ));
+
+    let load_palette = gio::SimpleAction::new("load-palette", None);
+    load_palette.connect_activate(glib::clone!(
+        #[weak]
+        window,
+        #[weak]
+        widget,
+        move |_, _| {
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some("Palettes (.map, .ggr, .ugr)"));
+            for suffix in ["map", "ggr", "ugr"] {
+                filter.add_suffix(suffix);
+            }
+            let filters = gio::ListStore::new::<gtk::FileFilter>();
+            filters.append(&filter);
+
+            let dialog = gtk::FileDialog::builder()
+                .title("Load Palette")
+                .modal(true)
+                .filters(&filters)
+                .default_filter(&filter)
+                .build();
+
+            dialog.open(
+                Some(&window),
+                gio::Cancellable::NONE,
+                glib::clone!(
+                    #[weak]
+                    window,
+                    #[weak]
+                    widget,
+                    #[strong]
+                    palettes,
+                    #[strong]
+                    names,
+                    move |file| {
+                        let file = match file {
+                            Ok(file) => file,
+                            Err(err) if err.matches(gtk::DialogError::Dismissed) => return,
+                            Err(err) => {
+                                show_error(&window, "Failed to load palette", &err.to_string());
+                                return;
+                            }
+                        };
+                        let Some(path) = file.path() else {
+                            show_error(
+                                &window,
+                                "Failed to load palette",
+                                "Only local files are supported",
+                            );
+                            return;
+                        };
+
+                        let loaded = match widget::Palette::load(&path) {
+                            Ok(loaded) => loaded,
+                            Err(err) => {
+                                show_error(
+                                    &window,
+                                    "Failed to load palette",
+                                    &format!("{}: {err}", path.display()),
+                                );
+                                return;
+                            }
+                        };
+
+                        let first = loaded[0].clone();
+                        for palette in &loaded {
+                            names.append(palette.name());
+                        }
+                        palettes.borrow_mut().extend(loaded);
+                        widget.set_palette(first);
+                    }
+                ),
+            );
+        }
+    ));
+    window.add_action(&load_palette);
+
+    drop_down
+}
+
+/// Shows a modal error dialog on top of `window`
+fn show_error(window: &gtk::ApplicationWindow, message: &str, detail: &str) {
+    gtk::AlertDialog::builder()
+        .message(message)
+        .detail(detail)
+        .modal(true)
+        .build()
+        .show(Some(window));
+}
+
+/// Creates a drop-down that is bidirectionally bound to the enum property `property` of `object`
+fn enum_drop_down(object: &impl IsA<glib::Object>, property: &str) -> gtk::DropDown {
+    let pspec = object
+        .find_property(property)
+        .expect("object has no such property");
+    let enum_class =
+        glib::EnumClass::with_type(pspec.value_type()).expect("property is not an enum");
+
+    let names = enum_class
+        .values()
+        .iter()
+        .map(|value| value.name())
+        .collect::<Vec<_>>();
+    let values = enum_class
+        .values()
+        .iter()
+        .map(|value| value.value())
+        .collect::<Vec<_>>();
+
+    let drop_down = gtk::DropDown::from_strings(&names);
+
+    object
+        .bind_property(property, &drop_down, "selected")
+        .transform_to_with_values({
+            let values = values.clone();
+            move |_, value| {
+                let (_, value) = glib::EnumValue::from_value(value)?;
+                let position = values.iter().position(|v| *v == value.value())?;
+                Some((position as u32).to_value())
+            }
+        })
+        .transform_from_with_values(move |_, position| {
+            let position = position.get::<u32>().ok()?;
+            enum_class.to_value(*values.get(position as usize)?)
+        })
+        .bidirectional()
+        .sync_create()
+        .build();
+
+    drop_down
+}
+
 #[cfg(not(target_os = "android"))]
 fn main() -> gtk::glib::ExitCode {
     let application = make_application();
@@ -0,0 +1,445 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use super::floatexp::FloatExp;
+
+/// Arbitrary precision signed fixed-point number
+///
+/// The magnitude is stored as big-endian 32 bit limbs, the first limb being the integer part
+/// and all following limbs the fractional part. The number of fractional limbs is the
+/// precision of the number, and the result of arithmetic between two numbers has the
+/// precision of the more precise operand. Results are truncated towards zero.
+///
+/// The integer part is limited to 32 bits, which is plenty for iterating points that did not
+/// escape yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigFixed {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigFixed {
+    /// Zero with `precision` fractional limbs
+    pub fn zero(precision: usize) -> Self {
+        BigFixed {
+            negative: false,
+            limbs: vec![0; precision + 1],
+        }
+    }
+
+    /// Converts `value` to a fixed-point number with `precision` fractional limbs
+    ///
+    /// This is exact if the precision is high enough to hold all bits of `value`.
+    pub fn from_f64(value: f64, precision: usize) -> Self {
+        assert!(
+            value.is_finite() && value.abs() < u32::MAX as f64,
+            "value out of range"
+        );
+
+        let mut limbs = Vec::with_capacity(precision + 1);
+        let mut remainder = value.abs();
+        for _ in 0..=precision {
+            let limb = remainder.floor();
+            limbs.push(limb as u32);
+            // Both operations are exact
+            remainder = (remainder - limb) * 4294967296.0;
+        }
+
+        let mut res = BigFixed {
+            negative: value < 0.0,
+            limbs,
+        };
+        res.normalize_sign();
+        res
+    }
+
+    /// Converts `value` to a fixed-point number with `precision` fractional limbs
+    ///
+    /// Like [`BigFixed::from_f64`] this is exact if the precision is high enough.
+    pub fn from_float_exp(value: FloatExp, precision: usize) -> Self {
+        let mantissa = value.mantissa();
+        assert!(mantissa.is_finite(), "value out of range");
+
+        // The mantissa as 53 bit integer, and the position of its lowest bit in the
+        // fixed-point representation
+        let bits = (mantissa.abs() * (1u64 << 53) as f64) as u64;
+        let shift = value.exponent() - 53 + 32 * precision as i64;
+
+        let (bits, shift) = if shift < 0 {
+            (bits.checked_shr((-shift) as u32).unwrap_or(0), 0)
+        } else {
+            (bits, shift as usize)
+        };
+
+        // Little-endian limbs of the whole number, which are reversed at the end
+        let mut limbs = vec![0u32; precision + 1];
+        let shifted = (bits as u128) << (shift % 32);
+        for (i, limb) in (shift / 32..).zip([
+            shifted as u32,
+            (shifted >> 32) as u32,
+            (shifted >> 64) as u32,
+        ]) {
+            if i < limbs.len() {
+                limbs[i] = limb;
+            } else {
+                assert_eq!(limb, 0, "value out of range");
+            }
+        }
+        limbs.reverse();
+
+        let mut res = BigFixed {
+            negative: mantissa < 0.0,
+            limbs,
+        };
+        res.normalize_sign();
+        res
+    }
+
+    /// Closest `f64` to this number
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, limb| acc / 4294967296.0 + *limb as f64);
+
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Closest `FloatExp` to this number
+    ///
+    /// Unlike [`BigFixed::to_f64`] this keeps the full `f64` precision for tiny numbers.
+    pub fn to_float_exp(&self) -> FloatExp {
+        let Some(first) = self.limbs.iter().position(|limb| *limb != 0) else {
+            return FloatExp::from_f64(0.0);
+        };
+
+        // Three limbs are more than enough for the 53 bits of the mantissa
+        let limbs = &self.limbs[first..usize::min(first + 3, self.limbs.len())];
+        let magnitude = limbs
+            .iter()
+            .fold(0.0, |acc, limb| acc * 4294967296.0 + *limb as f64);
+        let exponent = -32 * (first + limbs.len() - 1) as i64;
+
+        if self.negative {
+            FloatExp::new(-magnitude, exponent)
+        } else {
+            FloatExp::new(magnitude, exponent)
+        }
+    }
+
+    /// Number of fractional limbs
+    pub fn precision(&self) -> usize {
+        self.limbs.len() - 1
+    }
+
+    /// Returns a copy with exactly `precision` fractional limbs, truncating if necessary
+    pub fn with_precision(&self, precision: usize) -> Self {
+        let mut limbs = self.limbs.clone();
+        limbs.resize(precision + 1, 0);
+
+        let mut res = BigFixed {
+            negative: self.negative,
+            limbs,
+        };
+        res.normalize_sign();
+        res
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.iter().all(|limb| *limb == 0)
+    }
+
+    pub fn square(&self) -> Self {
+        self * self
+    }
+
+    /// Multiplies by two
+    pub fn double(&self) -> Self {
+        let mut limbs = self.limbs.clone();
+        let mut carry = 0;
+        for limb in limbs.iter_mut().rev() {
+            let next_carry = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next_carry;
+        }
+        assert_eq!(carry, 0, "overflow");
+
+        BigFixed {
+            negative: self.negative,
+            limbs,
+        }
+    }
+
+    /// Zero has no sign
+    fn normalize_sign(&mut self) {
+        if self.is_zero() {
+            self.negative = false;
+        }
+    }
+
+    /// Extends both operands to the same precision
+    fn aligned<'a>(
+        a: &'a BigFixed,
+        b: &'a BigFixed,
+    ) -> (std::borrow::Cow<'a, [u32]>, std::borrow::Cow<'a, [u32]>) {
+        use std::borrow::Cow;
+
+        match a.limbs.len().cmp(&b.limbs.len()) {
+            Ordering::Equal => (Cow::Borrowed(&a.limbs), Cow::Borrowed(&b.limbs)),
+            Ordering::Less => {
+                let mut limbs = a.limbs.clone();
+                limbs.resize(b.limbs.len(), 0);
+                (Cow::Owned(limbs), Cow::Borrowed(&b.limbs))
+            }
+            Ordering::Greater => {
+                let mut limbs = b.limbs.clone();
+                limbs.resize(a.limbs.len(), 0);
+                (Cow::Borrowed(&a.limbs), Cow::Owned(limbs))
+            }
+        }
+    }
+
+    fn add_signed(a: &BigFixed, b: &BigFixed, b_negative: bool) -> BigFixed {
+        let (a_limbs, b_limbs) = Self::aligned(a, b);
+
+        let mut res = if a.negative == b_negative {
+            BigFixed {
+                negative: a.negative,
+                limbs: add_magnitude(&a_limbs, &b_limbs),
+            }
+        } else {
+            match a_limbs.cmp(&b_limbs) {
+                Ordering::Less => BigFixed {
+                    negative: b_negative,
+                    limbs: sub_magnitude(&b_limbs, &a_limbs),
+                },
+                _ => BigFixed {
+                    negative: a.negative,
+                    limbs: sub_magnitude(&a_limbs, &b_limbs),
+                },
+            }
+        };
+        res.normalize_sign();
+        res
+    }
+}
+
+/// Sum of two magnitudes of the same length
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0; a.len()];
+    let mut carry = 0;
+    for ((res, a), b) in res.iter_mut().zip(a).zip(b).rev() {
+        let sum = *a as u64 + *b as u64 + carry;
+        *res = sum as u32;
+        carry = sum >> 32;
+    }
+    assert_eq!(carry, 0, "overflow");
+
+    res
+}
+
+/// Difference of two magnitudes of the same length, `a` must not be smaller than `b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0; a.len()];
+    let mut borrow = 0;
+    for ((res, a), b) in res.iter_mut().zip(a).zip(b).rev() {
+        let (diff, borrow_a) = a.overflowing_sub(*b);
+        let (diff, borrow_b) = diff.overflowing_sub(borrow);
+        *res = diff;
+        borrow = (borrow_a || borrow_b) as u32;
+    }
+    debug_assert_eq!(borrow, 0);
+
+    res
+}
+
+/// Product of two magnitudes of the same length, truncated to the same length
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let n = a.len();
+
+    // Full product with two integer limbs and twice the fractional limbs
+    let mut product = vec![0u32; 2 * n];
+    for i in (0..n).rev() {
+        if a[i] == 0 {
+            continue;
+        }
+
+        let mut carry = 0;
+        for j in (0..n).rev() {
+            let k = i + j + 1;
+            let t = a[i] as u64 * b[j] as u64 + product[k] as u64 + carry;
+            product[k] = t as u32;
+            carry = t >> 32;
+        }
+        product[i] = carry as u32;
+    }
+    assert_eq!(product[0], 0, "overflow");
+
+    product[1..=n].to_vec()
+}
+
+impl Add for &BigFixed {
+    type Output = BigFixed;
+
+    fn add(self, other: &BigFixed) -> BigFixed {
+        BigFixed::add_signed(self, other, other.negative)
+    }
+}
+
+impl Sub for &BigFixed {
+    type Output = BigFixed;
+
+    fn sub(self, other: &BigFixed) -> BigFixed {
+        BigFixed::add_signed(self, other, !other.negative)
+    }
+}
+
+impl Mul for &BigFixed {
+    type Output = BigFixed;
+
+    fn mul(self, other: &BigFixed) -> BigFixed {
+        let (a_limbs, b_limbs) = BigFixed::aligned(self, other);
+
+        let mut res = BigFixed {
+            negative: self.negative != other.negative,
+            limbs: mul_magnitude(&a_limbs, &b_limbs),
+        };
+        res.normalize_sign();
+        res
+    }
+}
+
+impl Neg for &BigFixed {
+    type Output = BigFixed;
+
+    fn neg(self) -> BigFixed {
+        let mut res = BigFixed {
+            negative: !self.negative,
+            limbs: self.limbs.clone(),
+        };
+        res.normalize_sign();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f64_round_trip() {
+        for &value in &[
+            0.0,
+            1.0,
+            -1.0,
+            1.5,
+            -0.75,
+            3.0e-10,
+            -1234.5678,
+            4294967294.5,
+        ] {
+            assert_eq!(BigFixed::from_f64(value, 3).to_f64(), value);
+        }
+
+        // Values with more bits than the precision are truncated towards zero
+        assert_eq!(BigFixed::from_f64(1.0 + 2e-16, 1).to_f64(), 1.0);
+        assert_eq!(BigFixed::from_f64(-1.0 - 2e-16, 1).to_f64(), -1.0);
+    }
+
+    #[test]
+    fn float_exp_round_trip() {
+        for &(mantissa, exponent) in &[(0.5, 0), (-0.75, 10), (0.9, -100), (-0.6, -1000)] {
+            let value = FloatExp::new(mantissa, exponent);
+            assert_eq!(BigFixed::from_float_exp(value, 40).to_float_exp(), value);
+        }
+
+        // Bits below the precision are dropped
+        let tiny = FloatExp::new(1.0, -200);
+        assert!(BigFixed::from_float_exp(tiny, 4).is_zero());
+        assert_eq!(
+            BigFixed::from_float_exp(FloatExp::from_f64(0.0), 4),
+            BigFixed::zero(4)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "value out of range")]
+    fn f64_overflow() {
+        BigFixed::from_f64(1e10, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "value out of range")]
+    fn float_exp_overflow() {
+        BigFixed::from_float_exp(FloatExp::new(1.0, 40), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow")]
+    fn addition_overflow() {
+        let large = BigFixed::from_f64(4294967294.0, 1);
+        let _ = &large + &large;
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = BigFixed::from_f64(1.5, 2);
+        let b = BigFixed::from_f64(-2.25, 2);
+
+        assert_eq!((&a + &b).to_f64(), -0.75);
+        assert_eq!((&a - &b).to_f64(), 3.75);
+        assert_eq!((&b - &a).to_f64(), -3.75);
+        assert_eq!((&a * &b).to_f64(), -3.375);
+        assert_eq!(b.square().to_f64(), 5.0625);
+        assert_eq!(b.double().to_f64(), -4.5);
+        assert_eq!((-&b).to_f64(), 2.25);
+
+        // Zero has no sign, so it compares equal to the positive zero
+        assert_eq!(&a - &a, BigFixed::zero(2));
+        assert_eq!(&b * &BigFixed::zero(2), BigFixed::zero(2));
+    }
+
+    #[test]
+    fn carries_between_limbs() {
+        // 1 - 2^-64 has all fractional bits of two limbs set
+        let almost_one =
+            &BigFixed::from_f64(1.0, 2) - &BigFixed::from_float_exp(FloatExp::new(1.0, -64), 2);
+        assert_eq!(almost_one.limbs, [0, u32::MAX, u32::MAX]);
+
+        let lowest = BigFixed::from_float_exp(FloatExp::new(1.0, -64), 2);
+        assert_eq!((&almost_one + &lowest).limbs, [1, 0, 0]);
+
+        // (1 - 2^-64)² = 1 - 2^-63 + 2^-128, which is truncated to 1 - 2^-63
+        assert_eq!(almost_one.square().limbs, [0, u32::MAX, u32::MAX - 1]);
+    }
+
+    #[test]
+    fn precision() {
+        let coarse = BigFixed::from_f64(0.5, 1);
+        let fine = BigFixed::from_float_exp(FloatExp::new(1.0, -40), 3);
+
+        // Results have the precision of the more precise operand
+        let sum = &coarse + &fine;
+        assert_eq!(sum.precision(), 3);
+        assert_eq!(
+            sum.to_float_exp(),
+            FloatExp::from_f64(0.5) + FloatExp::new(1.0, -40)
+        );
+
+        // Truncating drops the bits below the new precision
+        assert_eq!(sum.with_precision(1), coarse);
+        assert_eq!(sum.with_precision(4).precision(), 4);
+        assert_eq!(sum.with_precision(4).to_float_exp(), sum.to_float_exp());
+
+        // 1/3 in binary is 0.0101…, so its square is below 1/9 by less than the precision
+        let third = BigFixed::from_f64(1.0 / 3.0, 1);
+        let ninth = third.square();
+        assert!(ninth.to_f64() <= 1.0 / 9.0);
+        assert!(1.0 / 9.0 - ninth.to_f64() < 1e-9);
+    }
+}
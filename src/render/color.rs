@@ -0,0 +1,674 @@
+use gtk::glib;
+
+use once_cell::sync::Lazy;
+
+use std::f64::consts::PI;
+
+use num_complex::Complex64;
+
+use super::orbit::{ColoringAlgorithm, OrbitStatistic, TrackingOptions};
+use super::palette::Palette;
+use super::{Frame, Sample};
+
+#[cfg(target_endian = "big")]
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, zerocopy::IntoBytes, zerocopy::Immutable)]
+pub struct Pixel {
+    b: u8,
+    g: u8,
+    r: u8,
+    a: u8,
+}
+#[cfg(target_endian = "little")]
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, zerocopy::IntoBytes, zerocopy::Immutable)]
+pub struct Pixel {
+    #[allow(dead_code)]
+    a: u8,
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Default for Pixel {
+    fn default() -> Self {
+        Pixel {
+            a: 255,
+            r: 0,
+            g: 0,
+            b: 0,
+        }
+    }
+}
+
+/// Parameters of the coloring pass, which can be changed without iterating again
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coloring {
+    pub palette: Palette,
+    /// Algorithm that determines the palette position of escaped points
+    pub algorithm: ColoringAlgorithm,
+    /// Number of stripes per turn of the stripe average
+    pub stripe_density: f64,
+    /// Center of the orbit traps
+    pub trap_center: Complex64,
+    /// Angle of the line and cross traps in degrees
+    pub trap_angle: f64,
+    /// Number of iterations after which the palette repeats
+    pub period: f64,
+    /// Offset into the palette, as fraction of its period
+    pub offset: f64,
+    /// Whether the palette is traversed in reverse
+    pub reversed: bool,
+    /// Color space in which colors between the palette stops are blended
+    pub interpolation: Interpolation,
+    /// How iteration counts are mapped to positions in the palette by the escape time algorithm
+    pub mapping: ColorMapping,
+    /// Weight of the histogram-equalized position against the smooth iteration count, between
+    /// 0.0 and 1.0, if the histogram mapping is used
+    pub histogram_blend: f64,
+    /// How the estimated distance to the boundary of the set is used, if at all
+    pub distance_estimation: DistanceEstimation,
+    /// Distance to the boundary in pixels below which points are drawn as part of it
+    pub boundary_width: f64,
+    /// Whether the palette colors are shaded as if the image was a surface lit from the side
+    pub lighting: bool,
+    /// Direction the light comes from in degrees, counter-clockwise from the right
+    pub light_angle: f64,
+    /// Height of the light above the surface, relative to its distance
+    pub light_height: f64,
+    /// How points that did not escape are colored
+    pub interior: InteriorColoring,
+}
+
+/// How points that did not escape before the iteration limit are colored
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotInteriorColoring")]
+pub enum InteriorColoring {
+    /// Black, or white for line art
+    #[enum_value(name = "Solid")]
+    Solid,
+    /// Magnitude of the last value of the orbit
+    #[enum_value(name = "Final magnitude")]
+    Magnitude,
+    /// Period of the attracting cycle of the orbit
+    #[enum_value(name = "Period")]
+    Period,
+    /// Estimated distance to the boundary of the set from the inside
+    #[enum_value(name = "Distance")]
+    Distance,
+    /// Iteration at which the orbit came closest to zero
+    #[enum_value(name = "Atom domain")]
+    AtomDomain,
+}
+
+/// How the estimated distance of escaped points to the boundary of the set is used
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotDistanceEstimation")]
+pub enum DistanceEstimation {
+    /// Don't estimate the distance
+    #[enum_value(name = "Off")]
+    Off,
+    /// Darken the palette colors close to the boundary, which makes filaments thinner than a
+    /// pixel visible
+    #[enum_value(name = "Shaded")]
+    Shaded,
+    /// Draw only the boundary in black on white
+    #[enum_value(name = "Line art")]
+    LineArt,
+}
+
+/// How iteration counts are mapped to positions in the palette
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotColorMapping")]
+pub enum ColorMapping {
+    /// Repeat the palette every `period` smooth iterations
+    #[enum_value(name = "Smooth")]
+    Smooth,
+    /// Spread the palette over the distribution of iteration counts in the frame, so that
+    /// every color covers about the same area
+    #[enum_value(name = "Histogram")]
+    Histogram,
+}
+
+/// Cumulative distribution of the iteration counts of the escaped points of a frame
+#[derive(Debug)]
+pub(super) struct Histogram {
+    /// Fraction of escaped points with fewer iterations than the index
+    cumulative: Vec<f64>,
+}
+
+/// Color space in which two colors are blended
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotInterpolation")]
+pub enum Interpolation {
+    /// Blend the gamma-encoded sRGB components
+    #[enum_value(name = "sRGB")]
+    Srgb,
+    /// Blend the physical light intensities
+    #[enum_value(name = "Linear RGB")]
+    LinearRgb,
+    /// Blend in the perceptually uniform OKLab space
+    #[enum_value(name = "OKLab")]
+    Oklab,
+    /// Blend lightness, chroma and hue of OKLab, going around the shorter way of the hue circle
+    #[enum_value(name = "OKLCh")]
+    Oklch,
+}
+
+/// Linear intensities of all 8-bit sRGB component values
+static SRGB_TO_LINEAR: Lazy<[f64; 256]> = Lazy::new(|| {
+    std::array::from_fn(|value| {
+        let value = value as f64 / 255.0;
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    })
+});
+
+impl Pixel {
+    pub(super) const fn new(r: u8, g: u8, b: u8) -> Self {
+        Pixel { a: 255, r, g, b }
+    }
+
+    /// Blends from `self` at `frac` 0.0 to `other` at `frac` 1.0 in the given color space
+    pub fn interpolate(self, other: Self, frac: f64, interpolation: Interpolation) -> Self {
+        let lerp = |a: [f64; 3], b: [f64; 3]| -> [f64; 3] {
+            std::array::from_fn(|i| a[i] + frac * (b[i] - a[i]))
+        };
+
+        match interpolation {
+            Interpolation::Srgb => self.interpolate_srgb(other, frac),
+            Interpolation::LinearRgb => {
+                Pixel::from_linear(lerp(self.to_linear(), other.to_linear()))
+            }
+            Interpolation::Oklab => Pixel::from_linear(oklab_to_linear(lerp(
+                linear_to_oklab(self.to_linear()),
+                linear_to_oklab(other.to_linear()),
+            ))),
+            Interpolation::Oklch => {
+                let [l0, c0, h0] = oklab_to_oklch(linear_to_oklab(self.to_linear()));
+                let [l1, c1, h1] = oklab_to_oklch(linear_to_oklab(other.to_linear()));
+
+                // Grays have no meaningful hue, so only the chroma changes towards them
+                const ACHROMATIC: f64 = 1.0e-4;
+                let (h0, h1) = match (c0 < ACHROMATIC, c1 < ACHROMATIC) {
+                    (true, false) => (h1, h1),
+                    (false, true) => (h0, h0),
+                    _ => (h0, h1),
+                };
+                let hue_difference = (h1 - h0 + PI).rem_euclid(2.0 * PI) - PI;
+
+                Pixel::from_linear(oklab_to_linear(oklch_to_oklab([
+                    l0 + frac * (l1 - l0),
+                    c0 + frac * (c1 - c0),
+                    h0 + frac * hue_difference,
+                ])))
+            }
+        }
+    }
+
+    fn interpolate_srgb(self, other: Self, frac: f64) -> Self {
+        Pixel::new(
+            f64::clamp(
+                self.r as f64 + (frac * (other.r as f64 - self.r as f64)),
+                0.0,
+                255.0,
+            ) as u8,
+            f64::clamp(
+                self.g as f64 + (frac * (other.g as f64 - self.g as f64)),
+                0.0,
+                255.0,
+            ) as u8,
+            f64::clamp(
+                self.b as f64 + (frac * (other.b as f64 - self.b as f64)),
+                0.0,
+                255.0,
+            ) as u8,
+        )
+    }
+
+    /// Linear RGB intensities between 0.0 and 1.0
+    fn to_linear(self) -> [f64; 3] {
+        [self.r, self.g, self.b].map(|c| SRGB_TO_LINEAR[c as usize])
+    }
+
+    /// Gamma-encodes linear RGB intensities, clipping colors outside the sRGB gamut
+    fn from_linear(rgb: [f64; 3]) -> Self {
+        let [r, g, b] = rgb.map(|c| {
+            let c = c.clamp(0.0, 1.0);
+            let c = if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            };
+            (c * 255.0).round() as u8
+        });
+
+        Pixel::new(r, g, b)
+    }
+}
+
+// Conversions between linear sRGB and OKLab, see https://bottosson.github.io/posts/oklab/
+
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l_ - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l_ + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l_ - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
+
+/// Lightness, chroma and hue in radians
+fn oklab_to_oklch([l, a, b]: [f64; 3]) -> [f64; 3] {
+    [l, a.hypot(b), b.atan2(a)]
+}
+
+fn oklch_to_oklab([l, c, h]: [f64; 3]) -> [f64; 3] {
+    [l, c * h.cos(), c * h.sin()]
+}
+
+impl From<[u8; 3]> for Pixel {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Pixel::new(r, g, b)
+    }
+}
+
+/// Difference between the palette positions of consecutive periods and atom domains
+///
+/// The golden ratio spreads them evenly over the palette without ever repeating.
+const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+/// Number of iterations after which the color of the root a point converged to is half as
+/// bright
+const CONVERGENCE_HALF_LIFE: f64 = 10.0;
+
+impl Coloring {
+    /// What has to be calculated for every point in addition to its escape time for this
+    /// coloring
+    pub(super) fn tracking_options(&self) -> TrackingOptions {
+        TrackingOptions {
+            derivative: self.distance_estimation != DistanceEstimation::Off || self.lighting,
+            statistic: self.orbit_statistic(),
+            atom_domain: self.interior == InteriorColoring::AtomDomain,
+            cycle: matches!(
+                self.interior,
+                InteriorColoring::Period | InteriorColoring::Distance
+            ),
+            interior: self.interior != InteriorColoring::Solid,
+        }
+    }
+
+    /// Statistic that has to be accumulated over the orbits for this coloring, if any
+    fn orbit_statistic(&self) -> Option<OrbitStatistic> {
+        let (center, angle) = (self.trap_center, self.trap_angle.to_radians());
+
+        match self.algorithm {
+            ColoringAlgorithm::EscapeTime => None,
+            ColoringAlgorithm::TriangleInequality => Some(OrbitStatistic::TriangleInequality),
+            ColoringAlgorithm::Stripe => Some(OrbitStatistic::Stripe {
+                density: self.stripe_density,
+            }),
+            ColoringAlgorithm::Curvature => Some(OrbitStatistic::Curvature),
+            ColoringAlgorithm::PointTrap => Some(OrbitStatistic::PointTrap { center }),
+            ColoringAlgorithm::LineTrap => Some(OrbitStatistic::LineTrap { center, angle }),
+            ColoringAlgorithm::CrossTrap => Some(OrbitStatistic::CrossTrap { center, angle }),
+        }
+    }
+
+    /// Whether the samples rendered for `previous` lack anything this coloring uses, so that
+    /// switching from it needs the view to be rendered again instead of only re-colored
+    pub fn requires_render(&self, previous: &Coloring) -> bool {
+        !previous.tracking_options().covers(&self.tracking_options())
+    }
+
+    /// Color of a pixel with the given sample
+    ///
+    /// The histogram mapping falls back to the smooth mapping without a `histogram`.
+    pub(super) fn color(&self, sample: &Sample, histogram: Option<&Histogram>) -> Pixel {
+        const WHITE: Pixel = Pixel::new(255, 255, 255);
+
+        if sample.interior {
+            return match (self.interior, self.distance_estimation) {
+                (InteriorColoring::Solid, DistanceEstimation::LineArt) => WHITE,
+                (InteriorColoring::Solid, _) => Pixel::default(),
+                _ => self.interior_color(sample),
+            };
+        }
+
+        // Anti-aliased boundary, the distance is infinite if it was not estimated
+        let boundary = (sample.distance as f64 / self.boundary_width).clamp(0.0, 1.0);
+
+        match self.distance_estimation {
+            DistanceEstimation::Off => self.palette_color(sample, histogram),
+            DistanceEstimation::Shaded => Pixel::default().interpolate(
+                self.palette_color(sample, histogram),
+                boundary,
+                Interpolation::Srgb,
+            ),
+            DistanceEstimation::LineArt => {
+                Pixel::default().interpolate(WHITE, boundary, Interpolation::Srgb)
+            }
+        }
+    }
+
+    /// Color of the palette for the iterations of an escaped point, lit if enabled
+    fn palette_color(&self, sample: &Sample, histogram: Option<&Histogram>) -> Pixel {
+        let color = self.unlit_palette_color(sample, histogram);
+        if !self.lighting {
+            return color;
+        }
+
+        // Lambertian shading of the normal map, where the image is treated as a surface
+        // whose slope points along the normal. The imaginary axis points downwards in the
+        // image, so the light angle is mirrored.
+        let angle = -self.light_angle.to_radians();
+        let brightness =
+            ((sample.normal as f64 - angle).cos() + self.light_height) / (1.0 + self.light_height);
+
+        Pixel::default().interpolate(color, brightness.clamp(0.0, 1.0), Interpolation::Srgb)
+    }
+
+    fn unlit_palette_color(&self, sample: &Sample, histogram: Option<&Histogram>) -> Pixel {
+        if sample.root > 0 {
+            return self.root_color(sample);
+        }
+
+        let position = if self.algorithm == ColoringAlgorithm::EscapeTime {
+            let smooth_iterations = sample.smooth_iterations();
+            let position = smooth_iterations / self.period;
+            match (self.mapping, histogram) {
+                (ColorMapping::Histogram, Some(histogram)) => {
+                    self.histogram_blend * histogram.rank(smooth_iterations)
+                        + (1.0 - self.histogram_blend) * position
+                }
+                _ => position,
+            }
+        } else if self
+            .orbit_statistic()
+            .is_some_and(|statistic| statistic.is_trap())
+        {
+            // Trap distances span many orders of magnitude, so repeat the palette every time
+            // the orbit comes ten times closer
+            -(sample.statistic as f64).max(f64::MIN_POSITIVE).log10()
+        } else {
+            // Averages are between 0.0 and 1.0
+            sample.statistic as f64
+        };
+
+        self.palette_at(position)
+    }
+
+    /// Color of the palette for the root that Newton's method converged to, darker the longer
+    /// it took
+    ///
+    /// Like periods, the roots are spread over the palette by the golden ratio.
+    fn root_color(&self, sample: &Sample) -> Pixel {
+        let color = self.palette_at(sample.root as f64 * GOLDEN_RATIO_CONJUGATE);
+        let brightness = f64::powf(0.5, sample.smooth_iterations() / CONVERGENCE_HALF_LIFE);
+
+        Pixel::default().interpolate(color, brightness, Interpolation::Srgb)
+    }
+
+    /// Color of the palette for a point that did not escape
+    ///
+    /// Points whose cycle was not found, usually because they are too close to the boundary,
+    /// are black.
+    fn interior_color(&self, sample: &Sample) -> Pixel {
+        let position = match self.interior {
+            InteriorColoring::Solid => return Pixel::default(),
+            InteriorColoring::Magnitude => sample.magnitude as f64,
+            InteriorColoring::Period if sample.period > 0 => {
+                sample.period as f64 * GOLDEN_RATIO_CONJUGATE
+            }
+            // Repeat the palette every time the distance grows tenfold, like for traps
+            InteriorColoring::Distance if sample.distance.is_finite() => {
+                (sample.distance as f64).max(f64::MIN_POSITIVE).log10()
+            }
+            InteriorColoring::AtomDomain => sample.atom_domain as f64 * GOLDEN_RATIO_CONJUGATE,
+            InteriorColoring::Period | InteriorColoring::Distance => return Pixel::default(),
+        };
+
+        self.palette_at(position)
+    }
+
+    /// Color at `position` in the palette, after applying the offset and direction
+    fn palette_at(&self, position: f64) -> Pixel {
+        let position = (position + self.offset).rem_euclid(1.0);
+        if self.reversed {
+            self.palette.color_at(1.0 - position, self.interpolation)
+        } else {
+            self.palette.color_at(position, self.interpolation)
+        }
+    }
+}
+
+impl Histogram {
+    /// Distribution of the iteration counts that were counted while rendering `frame`
+    pub(super) fn new(frame: &Frame) -> Self {
+        let stats = &frame.stats;
+        let total = usize::max(stats.escaped, 1) as f64;
+        let mut sum = 0;
+        // One more entry for the fraction of all points, so that the slowest ones interpolate
+        // towards it
+        let cumulative = stats
+            .counts
+            .iter()
+            .chain(std::iter::repeat(&0))
+            .take(stats.max_escaped as usize + 2)
+            .map(|&count| {
+                let fraction = sum as f64 / total;
+                sum += count;
+                fraction
+            })
+            .collect();
+
+        Histogram { cumulative }
+    }
+
+    /// Fraction of escaped points with fewer iterations, interpolated for smooth iterations
+    ///
+    /// This stays below 1.0 so that the slowest points are not wrapped around to the start of
+    /// the palette.
+    fn rank(&self, smooth_iterations: f64) -> f64 {
+        let last = self.cumulative.len() - 1;
+        let smooth_iterations = smooth_iterations.clamp(0.0, last as f64);
+        let index = usize::min(smooth_iterations as usize, last - 1);
+        let frac = smooth_iterations - index as f64;
+
+        let rank =
+            self.cumulative[index] + frac * (self.cumulative[index + 1] - self.cumulative[index]);
+        f64::min(rank, 1.0 - f64::EPSILON)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::palette::Stop;
+    use super::super::{iteration_stats, Fractal, View};
+    use super::*;
+
+    use rayon::prelude::*;
+
+    fn rgb(pixel: Pixel) -> [u8; 3] {
+        [pixel.r, pixel.g, pixel.b]
+    }
+
+    fn assert_close(a: [f64; 3], b: [f64; 3], tolerance: f64) {
+        for (a_i, b_i) in a.iter().zip(&b) {
+            assert!((a_i - b_i).abs() < tolerance, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn srgb_transfer() {
+        for value in 0..=255 {
+            let pixel = Pixel::new(value, value, value);
+            assert_eq!(rgb(Pixel::from_linear(pixel.to_linear())), [value; 3]);
+        }
+
+        // Reference values of the sRGB transfer function
+        assert_eq!(Pixel::new(0, 0, 0).to_linear(), [0.0; 3]);
+        assert_eq!(Pixel::new(255, 255, 255).to_linear(), [1.0; 3]);
+        assert_close(
+            Pixel::new(10, 128, 188).to_linear(),
+            [0.003035, 0.215861, 0.502886],
+            1e-6,
+        );
+
+        // Out of gamut colors are clipped
+        assert_eq!(rgb(Pixel::from_linear([-0.5, 0.5, 1.5])), [0, 188, 255]);
+    }
+
+    #[test]
+    fn oklab_reference_values() {
+        // OKLab coordinates of the sRGB primaries and white, as published by its author
+        for &(color, oklab) in &[
+            ([255, 255, 255], [1.0, 0.0, 0.0]),
+            ([255, 0, 0], [0.627955, 0.224863, 0.125846]),
+            ([0, 255, 0], [0.866440, -0.233888, 0.179498]),
+            ([0, 0, 255], [0.452014, -0.032457, -0.311528]),
+        ] {
+            let linear = Pixel::from(color).to_linear();
+            assert_close(linear_to_oklab(linear), oklab, 1e-5);
+            assert_close(oklab_to_linear(oklab), linear, 1e-5);
+        }
+
+        // Red in OKLCh, with the hue in degrees
+        let [l, c, h] = oklab_to_oklch(linear_to_oklab(Pixel::new(255, 0, 0).to_linear()));
+        assert_close([l, c, h.to_degrees()], [0.627955, 0.257683, 29.2339], 1e-4);
+    }
+
+    #[test]
+    fn oklab_round_trip() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let linear = Pixel::new(r, g, b).to_linear();
+                    let oklab = linear_to_oklab(linear);
+                    // The published matrices are only inverses of each other up to their
+                    // ten digits, which is far below the precision of 8-bit colors
+                    assert_close(oklab_to_linear(oklab), linear, 1e-6);
+                    assert_eq!(rgb(Pixel::from_linear(oklab_to_linear(oklab))), [r, g, b]);
+                    assert_close(oklch_to_oklab(oklab_to_oklch(oklab)), oklab, 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let (a, b) = (Pixel::new(255, 0, 0), Pixel::new(0, 64, 255));
+        for &interpolation in &[
+            Interpolation::Srgb,
+            Interpolation::LinearRgb,
+            Interpolation::Oklab,
+            Interpolation::Oklch,
+        ] {
+            assert_eq!(rgb(a.interpolate(b, 0.0, interpolation)), rgb(a));
+            assert_eq!(rgb(a.interpolate(b, 1.0, interpolation)), rgb(b));
+        }
+
+        // Linear blending is brighter in the middle than blending the encoded values
+        assert_eq!(
+            rgb(Pixel::default().interpolate(Pixel::new(255, 255, 255), 0.5, Interpolation::Srgb)),
+            [127; 3]
+        );
+        assert_eq!(
+            rgb(Pixel::default().interpolate(
+                Pixel::new(255, 255, 255),
+                0.5,
+                Interpolation::LinearRgb
+            )),
+            [188; 3]
+        );
+
+        // Blending from black keeps the hue of the other color
+        let [_, _, hue] = oklab_to_oklch(linear_to_oklab(a.to_linear()));
+        let halfway = Pixel::default().interpolate(a, 0.5, Interpolation::Oklch);
+        let [_, chroma, halfway_hue] = oklab_to_oklch(linear_to_oklab(halfway.to_linear()));
+        assert!(chroma > 0.1);
+        assert!((halfway_hue - hue).abs() < 0.05);
+    }
+
+    #[test]
+    fn histogram() {
+        // One sample each for 0 to 9 iterations and as many interior ones
+        let samples = (0..20)
+            .map(|iterations| Sample {
+                iterations: u32::min(iterations, 10),
+                interior: iterations >= 10,
+                ..Sample::default()
+            })
+            .collect::<Vec<_>>();
+        let frame = Frame {
+            view: View::new(0.0, 0.0, 1.0, 1.0),
+            width: 20,
+            height: 1,
+            max_iterations: 10,
+            stats: iteration_stats(samples.par_iter(), 10),
+            tiles: vec![samples],
+            tracking: TrackingOptions::default(),
+            subdivided: false,
+            fractal: Fractal::Mandelbrot,
+            julia: None,
+        };
+        let histogram = Histogram::new(&frame);
+
+        // The counts of all parallel jobs are merged
+        let many = (0..100_000u32)
+            .map(|i| Sample {
+                iterations: i.wrapping_mul(2_654_435_761) % 1000,
+                ..Sample::default()
+            })
+            .collect::<Vec<_>>();
+        let mut counts = vec![0; 1000];
+        for sample in &many {
+            counts[sample.iterations as usize] += 1;
+        }
+        assert_eq!(iteration_stats(many.par_iter(), 1000).counts, counts);
+
+        assert_eq!(histogram.rank(0.0), 0.0);
+        assert_eq!(histogram.rank(5.0), 0.5);
+        assert!((histogram.rank(5.5) - 0.55).abs() < 1e-12);
+        assert!(histogram.rank(8.9) < histogram.rank(9.0));
+
+        // The slowest points get the end of the palette, not its start again
+        let palette = Palette::new(
+            "Black to white",
+            vec![
+                Stop {
+                    position: 0.0,
+                    color: [0, 0, 0],
+                },
+                Stop {
+                    position: 1.0,
+                    color: [255, 255, 255],
+                },
+            ],
+        );
+        for &smooth_iterations in &[10.0, 11.0, 1000.0] {
+            let rank = histogram.rank(smooth_iterations);
+            assert!(rank < 1.0);
+            let [r, g, b] = rgb(palette.color_at(rank.rem_euclid(1.0), Interpolation::Srgb));
+            assert!(r >= 254 && g >= 254 && b >= 254);
+        }
+    }
+}
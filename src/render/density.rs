@@ -0,0 +1,544 @@
+//! Density of the orbits of escaping points, like the Buddhabrot
+//!
+//! Instead of coloring every point by its own orbit, random points are iterated and every
+//! pixel gets brighter the more orbits pass through it. The image converges over time and is
+//! sent again and again while it is refined.
+//!
+//! Random points only rarely have orbits that pass through a small view, so the points are
+//! sampled by the Metropolis–Hastings algorithm: every rayon thread runs a chain of points
+//! that mostly moves by small steps and prefers points whose orbits pass through the view
+//! many times. Each orbit is weighted by the inverse of that preference, so the image is the
+//! same as with uniformly distributed points, only with less noise.
+
+use gtk::glib;
+
+use num_complex::Complex64;
+
+use rayon::prelude::*;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::color::Pixel;
+use super::{
+    in_main_components, CancellationToken, Command, Fractal, Frame, Tile, View,
+    PERIODICITY_TOLERANCE,
+};
+
+/// What is shown for every pixel
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotRenderMode")]
+pub enum RenderMode {
+    /// Escape time of the point at the pixel, colored by the coloring settings
+    #[enum_value(name = "Escape time")]
+    EscapeTime,
+    /// Density of the orbits of escaping points in gray
+    #[enum_value(name = "Buddhabrot")]
+    Buddhabrot,
+    /// Density of the orbits of escaping points with a different iteration limit for every
+    /// color channel
+    #[enum_value(name = "Nebulabrot")]
+    Nebulabrot,
+}
+
+/// Factors by which the iteration limits of the red, green and blue channels of the
+/// Nebulabrot are lower than the iteration limit
+const NEBULABROT_DIVISORS: [u32; 3] = [1, 10, 100];
+
+/// Time until the first image is sent, which doubles for every further one
+const FIRST_REFRESH: Duration = Duration::from_millis(100);
+
+/// Longest time between two refined images
+const MAX_REFRESH: Duration = Duration::from_secs(2);
+
+/// Average number of orbit points per pixel after which the image counts as converged and
+/// rendering stops
+///
+/// The noise of a pixel is about the inverse square root of its number of orbit points, so
+/// this is a bit below 1% on average.
+const MAX_POINTS_PER_PIXEL: u64 = 20_000;
+
+/// Time after which rendering stops even if the image did not converge, e.g. because hardly
+/// any orbits pass through the view
+const MAX_RENDER_TIME: Duration = Duration::from_secs(600);
+
+/// Probability of proposing a uniformly distributed point instead of one close to the
+/// current point of a chain
+const UNIFORM_PROBABILITY: f64 = 0.2;
+
+/// Largest distance of a proposed point from the current one, as fraction of the view size
+///
+/// The distance is distributed logarithmically over three orders of magnitude below this.
+const MUTATION_SIZE: f64 = 0.5;
+
+/// Factor by which the region random points are taken from is larger than the view that
+/// contains the whole set, which is a bit too tight for the tips of some sets
+const DOMAIN_MARGIN: f64 = 1.2;
+
+impl RenderMode {
+    /// Iteration limits of the red, green and blue channels for the iteration limit
+    /// `max_iterations`
+    fn channel_limits(self, max_iterations: u32) -> [u32; 3] {
+        match self {
+            RenderMode::Nebulabrot => {
+                NEBULABROT_DIVISORS.map(|divisor| u32::max(max_iterations / divisor, 1))
+            }
+            RenderMode::EscapeTime | RenderMode::Buddhabrot => [max_iterations; 3],
+        }
+    }
+}
+
+/// Sum of the weights of the orbit points that fell into every pixel, per color channel,
+/// shared between all threads
+struct Density {
+    width: usize,
+    height: usize,
+    /// Bits of the `f32` sums
+    sums: Vec<[AtomicU32; 3]>,
+}
+
+impl Density {
+    fn new(width: usize, height: usize) -> Self {
+        Density {
+            width,
+            height,
+            sums: (0..width * height)
+                .map(|_| std::array::from_fn(|_| AtomicU32::new(0)))
+                .collect(),
+        }
+    }
+
+    fn add(&self, pixel: usize, channel: usize, weight: f32) {
+        // Only fails if the closure returns `None`
+        let sum = &self.sums[pixel][channel];
+        let _ = sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+            Some((f32::from_bits(sum) + weight).to_bits())
+        });
+    }
+
+    fn sum(&self, pixel: usize, channel: usize) -> f32 {
+        f32::from_bits(self.sums[pixel][channel].load(Ordering::Relaxed))
+    }
+
+    /// Highest sum of every channel
+    fn maxima(&self) -> [f32; 3] {
+        (0..self.sums.len())
+            .into_par_iter()
+            .map(|pixel| std::array::from_fn(|channel| self.sum(pixel, channel)))
+            .reduce(
+                || [0.0; 3],
+                |a: [f32; 3], b| std::array::from_fn(|channel| f32::max(a[channel], b[channel])),
+            )
+    }
+
+    /// Sends the current image as tiles of `frame`
+    ///
+    /// The sums are scaled so that the highest one of every channel is white and are
+    /// brightened by a square root, as the density falls off steeply away from the set.
+    fn send(&self, frame: u64, max_iterations: u32, tiles: &async_channel::Sender<Tile>) {
+        let maxima = self.maxima();
+        let value = |pixel: usize, channel: usize| {
+            let sum = self.sum(pixel, channel);
+            if maxima[channel] > 0.0 {
+                (f32::sqrt(sum / maxima[channel]) * 255.0).round() as u8
+            } else {
+                0
+            }
+        };
+
+        Frame::tile_areas(self.width, self.height)
+            .into_par_iter()
+            .for_each(|area| {
+                let pixels = (area.y..area.y + area.height)
+                    .flat_map(|y| (area.x..area.x + area.width).map(move |x| y * self.width + x))
+                    .map(|pixel| Pixel::new(value(pixel, 0), value(pixel, 1), value(pixel, 2)))
+                    .collect();
+                let tile = Tile {
+                    pixels,
+                    x: area.x,
+                    y: area.y,
+                    width: area.width,
+                    height: area.height,
+                    scale: area.scale,
+                    frame,
+                    max_iterations,
+                };
+                tiles.send_blocking(tile).unwrap();
+            });
+    }
+}
+
+/// Everything that is needed for iterating orbits and finding the pixels they pass through
+struct Sampler {
+    fractal: Fractal,
+    julia: Option<Complex64>,
+    /// Iteration limits of the red, green and blue channels
+    limits: [u32; 3],
+    max_iterations: u32,
+    /// Region that random points are taken from, as its corner with the lowest coordinates
+    /// and its size
+    domain: (Complex64, Complex64),
+    /// Corner of the view that is at the first pixel, and pixels per unit
+    origin: Complex64,
+    scale: Complex64,
+    width: usize,
+    height: usize,
+    /// Largest distance of a proposed point from the current one
+    mutation_size: f64,
+}
+
+impl Sampler {
+    fn new(
+        view: &View,
+        (width, height): (usize, usize),
+        fractal: Fractal,
+        julia: Option<Complex64>,
+        limits: [u32; 3],
+    ) -> Self {
+        let domain = fractal.initial_view(julia.is_some());
+        let domain_size =
+            Complex64::new(domain.width().to_f64(), domain.height().to_f64()) * DOMAIN_MARGIN;
+        let domain_center = Complex64::new(domain.center_x().to_f64(), domain.center_y().to_f64());
+
+        let size = Complex64::new(view.width().to_f64(), view.height().to_f64());
+        let center = Complex64::new(view.center_x().to_f64(), view.center_y().to_f64());
+
+        Sampler {
+            fractal,
+            julia,
+            limits,
+            max_iterations: limits.iter().copied().max().unwrap_or(1),
+            domain: (domain_center - domain_size / 2.0, domain_size),
+            origin: center - size / 2.0,
+            // The first and last pixel are at the edges of the view like for escape times
+            scale: Complex64::new(
+                (width as f64 - 1.0) / size.re,
+                (height as f64 - 1.0) / size.im,
+            ),
+            width,
+            height,
+            mutation_size: MUTATION_SIZE * f64::max(size.re, size.im),
+        }
+    }
+
+    /// Index of the pixel that `z` falls into, if it is inside the view
+    #[inline]
+    fn pixel(&self, z: Complex64) -> Option<u32> {
+        let x = ((z.re - self.origin.re) * self.scale.re).round();
+        let y = ((z.im - self.origin.im) * self.scale.im).round();
+        (x >= 0.0 && y >= 0.0 && x < self.width as f64 && y < self.height as f64)
+            .then(|| (y as usize * self.width + x as usize) as u32)
+    }
+
+    fn in_domain(&self, point: Complex64) -> bool {
+        let (corner, size) = self.domain;
+        let relative = point - corner;
+        (0.0..size.re).contains(&relative.re) && (0.0..size.im).contains(&relative.im)
+    }
+
+    fn uniform(&self, rng: &mut fastrand::Rng) -> Complex64 {
+        let (corner, size) = self.domain;
+        corner + Complex64::new(rng.f64() * size.re, rng.f64() * size.im)
+    }
+
+    /// Point at a random distance in a random direction from `point`
+    fn mutate(&self, point: Complex64, rng: &mut fastrand::Rng) -> Complex64 {
+        let distance = self.mutation_size * f64::powf(10.0, -3.0 * rng.f64());
+        point + Complex64::from_polar(distance, std::f64::consts::TAU * rng.f64())
+    }
+
+    /// Iterates the orbit of `point`, or of the orbit starting at it for Julia sets, and
+    /// collects the pixels it passes through into `pixels`
+    ///
+    /// Returns the number of iterations until the orbit escaped, or `None` if it did not
+    /// escape before the highest iteration limit.
+    fn orbit(&self, point: Complex64, pixels: &mut Vec<u32>) -> Option<u32> {
+        pixels.clear();
+
+        let (mut z, c) = match self.julia {
+            Some(julia) => (point, julia),
+            None if self.fractal == Fractal::Mandelbrot && in_main_components(point) => {
+                return None;
+            }
+            None => (Complex64::new(0.0, 0.0), point),
+        };
+
+        // Orbits that become periodic never escape, like for escape times
+        let periodic = self.fractal.is_memoryless();
+        let mut check = z;
+        let mut next_check = 1;
+        let mut previous = Complex64::new(0.0, 0.0);
+        let mut it = 0;
+        while !self.fractal.escaped(z, c) {
+            if it >= self.max_iterations {
+                return None;
+            }
+
+            z = self.fractal.step(z, c, &mut previous);
+            it += 1;
+            if let Some(pixel) = self.pixel(z) {
+                pixels.push(pixel);
+            }
+
+            if periodic {
+                if (z - check).norm_sqr() < PERIODICITY_TOLERANCE {
+                    return None;
+                }
+                if it == next_check {
+                    check = z;
+                    next_check *= 2;
+                }
+            }
+        }
+
+        Some(it)
+    }
+}
+
+/// Markov chain of points whose orbits pass through the view
+struct Chain {
+    rng: fastrand::Rng,
+    /// Current point and the number of iterations until its orbit escaped
+    point: Complex64,
+    iterations: u32,
+    /// Pixels the orbit of the current point passes through, empty until a point was found
+    /// whose orbit passes through the view
+    pixels: Vec<u32>,
+    /// Pixels of the orbit of the proposed next point
+    proposed: Vec<u32>,
+    /// Number of orbit points added to the density so far
+    points: u64,
+}
+
+impl Chain {
+    fn new() -> Self {
+        Chain {
+            rng: fastrand::Rng::new(),
+            point: Complex64::new(0.0, 0.0),
+            iterations: 0,
+            pixels: Vec::new(),
+            proposed: Vec::new(),
+            points: 0,
+        }
+    }
+
+    /// Proposes a next point and adds the orbit of the point the chain is at afterwards to
+    /// `density`
+    ///
+    /// A proposed point is accepted with a probability of the ratio of how often its orbit
+    /// and the one of the current point pass through the view, so points are visited in
+    /// proportion to that. Proposals are symmetric as long as they stay inside the domain.
+    fn step(&mut self, sampler: &Sampler, density: &Density) {
+        let proposal = if self.pixels.is_empty() || self.rng.f64() < UNIFORM_PROBABILITY {
+            sampler.uniform(&mut self.rng)
+        } else {
+            sampler.mutate(self.point, &mut self.rng)
+        };
+
+        if sampler.in_domain(proposal) {
+            if let Some(iterations) = sampler.orbit(proposal, &mut self.proposed) {
+                let (current, proposed) = (self.pixels.len(), self.proposed.len());
+                if proposed > 0 && self.rng.f64() * current as f64 <= proposed as f64 {
+                    std::mem::swap(&mut self.pixels, &mut self.proposed);
+                    self.point = proposal;
+                    self.iterations = iterations;
+                }
+            }
+        }
+
+        if self.pixels.is_empty() {
+            return;
+        }
+
+        // Undo the preference for orbits that pass through the view often
+        let weight = 1.0 / self.pixels.len() as f32;
+        self.points += self.pixels.len() as u64;
+        for (channel, &limit) in sampler.limits.iter().enumerate() {
+            if self.iterations <= limit {
+                for &pixel in &self.pixels {
+                    density.add(pixel as usize, channel, weight);
+                }
+            }
+        }
+    }
+}
+
+/// Renders the density of the orbits of escaping points in `view` with `mode`
+///
+/// Refined images are sent as tiles of `frame` until the image converged, rendering is
+/// cancelled or another command is received, which is returned. Commands to recolor are
+/// ignored as the coloring is not used. The orbits are iterated with `f64`.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn render_density(
+    view: &View,
+    (target_width, target_height): (usize, usize),
+    max_iterations: u32,
+    mode: RenderMode,
+    fractal: Fractal,
+    julia: Option<Complex64>,
+    frame: u64,
+    cancel: &CancellationToken,
+    commands: &mpsc::Receiver<Command>,
+    tiles: &async_channel::Sender<Tile>,
+) -> Option<Command> {
+    let sampler = Sampler::new(
+        view,
+        (target_width, target_height),
+        fractal,
+        julia,
+        mode.channel_limits(max_iterations),
+    );
+    let density = Density::new(target_width, target_height);
+    let mut chains = (0..rayon::current_num_threads())
+        .map(|_| Chain::new())
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut refresh = FIRST_REFRESH;
+    loop {
+        let deadline = Instant::now() + refresh;
+        chains.par_iter_mut().for_each(|chain| {
+            while Instant::now() < deadline && !cancel.is_cancelled() {
+                chain.step(&sampler, &density);
+            }
+        });
+        if cancel.is_cancelled() {
+            return None;
+        }
+
+        density.send(frame, max_iterations, tiles);
+
+        while let Ok(command) = commands.try_recv() {
+            if !matches!(command, Command::Recolor { .. }) {
+                return Some(command);
+            }
+        }
+
+        let points = chains.iter().map(|chain| chain.points).sum::<u64>();
+        if points >= MAX_POINTS_PER_PIXEL * (target_width * target_height) as u64
+            || start.elapsed() >= MAX_RENDER_TIME
+        {
+            return None;
+        }
+
+        refresh = Duration::min(refresh * 2, MAX_REFRESH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges() {
+        let (_command_sender, commands) = mpsc::channel();
+        let (tiles, tile_receiver) = async_channel::unbounded();
+
+        let pending = render_density(
+            &View::new(-0.5, 0.0, 3.0, 3.0),
+            (8, 8),
+            100,
+            RenderMode::Nebulabrot,
+            Fractal::Mandelbrot,
+            None,
+            1,
+            &CancellationToken::default(),
+            &commands,
+            &tiles,
+        );
+
+        assert!(pending.is_none());
+        assert!(!tile_receiver.is_empty());
+    }
+
+    #[test]
+    fn channel_limits() {
+        assert_eq!(RenderMode::Buddhabrot.channel_limits(1000), [1000; 3]);
+        assert_eq!(RenderMode::Nebulabrot.channel_limits(1000), [1000, 100, 10]);
+        // No channel is left without any orbits
+        assert_eq!(RenderMode::Nebulabrot.channel_limits(50), [50, 5, 1]);
+    }
+
+    /// Sums of all pixels of `channel`, scaled to add up to one
+    fn distribution(density: &Density, channel: usize) -> Vec<f64> {
+        let sums = (0..density.sums.len())
+            .map(|pixel| density.sum(pixel, channel) as f64)
+            .collect::<Vec<_>>();
+        let total = sums.iter().sum::<f64>();
+        sums.into_iter().map(|sum| sum / total).collect()
+    }
+
+    /// Total variation distance between two distributions
+    fn distance(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f64>() / 2.0
+    }
+
+    /// The weighted orbits of the Metropolis–Hastings chain give the same density as the
+    /// orbits of uniformly distributed points, and the channels only contain the orbits
+    /// within their iteration limit
+    #[test]
+    fn matches_uniform_sampling() {
+        const SIZE: usize = 8;
+        const SAMPLES: usize = 400_000;
+
+        let sampler = Sampler::new(
+            &View::new(-0.5, 0.3, 1.5, 1.5),
+            (SIZE, SIZE),
+            Fractal::Mandelbrot,
+            None,
+            RenderMode::Nebulabrot.channel_limits(1000),
+        );
+
+        let uniform = Density::new(SIZE, SIZE);
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut pixels = Vec::new();
+        for _ in 0..SAMPLES {
+            if let Some(iterations) = sampler.orbit(sampler.uniform(&mut rng), &mut pixels) {
+                for (channel, &limit) in sampler.limits.iter().enumerate() {
+                    if iterations <= limit {
+                        for &pixel in &pixels {
+                            uniform.add(pixel as usize, channel, 1.0);
+                        }
+                    }
+                }
+            }
+        }
+
+        let weighted = Density::new(SIZE, SIZE);
+        let mut chain = Chain {
+            rng: fastrand::Rng::with_seed(2),
+            ..Chain::new()
+        };
+        // Same density without undoing the preference for long orbits through the view
+        let unweighted = Density::new(SIZE, SIZE);
+        for _ in 0..SAMPLES {
+            chain.step(&sampler, &weighted);
+            for &pixel in &chain.pixels {
+                unweighted.add(pixel as usize, 0, 1.0);
+            }
+        }
+
+        for channel in 0..3 {
+            let uniform = distribution(&uniform, channel);
+            let weighted = distribution(&weighted, channel);
+            let distance = distance(&uniform, &weighted);
+            assert!(distance < 0.05, "channel {}: {}", channel, distance);
+        }
+        let distance = distance(&distribution(&uniform, 0), &distribution(&unweighted, 0));
+        assert!(distance > 0.1, "{}", distance);
+
+        // Orbits that escape after more than a tenth of the limit only brighten red
+        assert!(uniform.sum(0, 0) >= uniform.sum(0, 1));
+        let red = (0..SIZE * SIZE)
+            .map(|pixel| uniform.sum(pixel, 0))
+            .sum::<f32>();
+        let green = (0..SIZE * SIZE)
+            .map(|pixel| uniform.sum(pixel, 1))
+            .sum::<f32>();
+        let blue = (0..SIZE * SIZE)
+            .map(|pixel| uniform.sum(pixel, 2))
+            .sum::<f32>();
+        assert!(red > green && green > blue && blue > 0.0);
+    }
+}
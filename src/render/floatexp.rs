@@ -0,0 +1,335 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+use num_traits::{Num, One, Zero};
+
+/// Floating point number with an extended exponent range
+///
+/// The value is `mantissa * 2^exponent` with the mantissa normalized to `0.5 <= |mantissa| < 1`,
+/// or zero. This has the precision of `f64` but can represent magnitudes far beyond the
+/// `f64` range, which is needed for the tiny differences between pixels of very deep zooms.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FloatExp {
+    mantissa: f64,
+    exponent: i64,
+}
+
+impl FloatExp {
+    pub fn new(mantissa: f64, exponent: i64) -> Self {
+        let (mantissa, e) = frexp(mantissa);
+
+        if mantissa == 0.0 {
+            FloatExp {
+                mantissa,
+                exponent: 0,
+            }
+        } else {
+            FloatExp {
+                mantissa,
+                exponent: exponent + e,
+            }
+        }
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        FloatExp::new(value, 0)
+    }
+
+    /// Closest `f64` to this number, which is zero or infinity if it is out of range
+    pub fn to_f64(self) -> f64 {
+        ldexp(self.mantissa, self.exponent)
+    }
+
+    pub fn mantissa(self) -> f64 {
+        self.mantissa
+    }
+
+    pub fn exponent(self) -> i64 {
+        self.exponent
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.mantissa.is_finite()
+    }
+
+    pub fn abs(self) -> Self {
+        FloatExp {
+            mantissa: self.mantissa.abs(),
+            ..self
+        }
+    }
+
+    pub fn min(self, other: FloatExp) -> Self {
+        if other < self {
+            other
+        } else {
+            self
+        }
+    }
+
+    pub fn log2(self) -> f64 {
+        self.mantissa.abs().log2() + self.exponent as f64
+    }
+
+    /// Rounds towards zero
+    pub fn trunc(self) -> Self {
+        if self.exponent <= 0 {
+            FloatExp::zero()
+        } else if self.exponent >= 53 {
+            self
+        } else {
+            FloatExp::from_f64(ldexp(self.mantissa, self.exponent).trunc())
+        }
+    }
+}
+
+/// Splits `value` into a mantissa with `0.5 <= |mantissa| < 1` and an exponent
+fn frexp(value: f64) -> (f64, i64) {
+    const EXPONENT_MASK: u64 = 0x7ff << 52;
+
+    if value == 0.0 || !value.is_finite() {
+        return (value, 0);
+    }
+
+    let bits = value.to_bits();
+    let biased_exponent = ((bits & EXPONENT_MASK) >> 52) as i64;
+    if biased_exponent == 0 {
+        // Subnormal, scale up into the normal range first
+        let (mantissa, exponent) = frexp(value * f64::from_bits((1023 + 64) << 52));
+        return (mantissa, exponent - 64);
+    }
+
+    let mantissa = f64::from_bits((bits & !EXPONENT_MASK) | (1022 << 52));
+    (mantissa, biased_exponent - 1022)
+}
+
+/// Computes `value * 2^exponent`
+fn ldexp(mut value: f64, mut exponent: i64) -> f64 {
+    // Scale in steps that are representable as `f64`
+    while exponent > 1000 && value.is_finite() && value != 0.0 {
+        value *= f64::from_bits((1023 + 1000) << 52);
+        exponent -= 1000;
+    }
+    while exponent < -1000 && value.is_finite() && value != 0.0 {
+        value *= f64::from_bits((1023 - 1000) << 52);
+        exponent += 1000;
+    }
+
+    value * f64::powi(2.0, exponent.clamp(-1100, 1100) as i32)
+}
+
+impl Add for FloatExp {
+    type Output = FloatExp;
+
+    fn add(self, other: FloatExp) -> FloatExp {
+        if self.mantissa == 0.0 {
+            return other;
+        } else if other.mantissa == 0.0 {
+            return self;
+        }
+
+        let (large, small) = if self.exponent >= other.exponent {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        // The smaller value does not contribute at all anymore
+        let difference = large.exponent - small.exponent;
+        if difference > 64 {
+            return large;
+        }
+
+        FloatExp::new(
+            large.mantissa + small.mantissa * f64::powi(2.0, -difference as i32),
+            large.exponent,
+        )
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = FloatExp;
+
+    fn sub(self, other: FloatExp) -> FloatExp {
+        self + -other
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = FloatExp;
+
+    fn mul(self, other: FloatExp) -> FloatExp {
+        FloatExp::new(
+            self.mantissa * other.mantissa,
+            self.exponent + other.exponent,
+        )
+    }
+}
+
+impl Div for FloatExp {
+    type Output = FloatExp;
+
+    fn div(self, other: FloatExp) -> FloatExp {
+        FloatExp::new(
+            self.mantissa / other.mantissa,
+            self.exponent - other.exponent,
+        )
+    }
+}
+
+impl Rem for FloatExp {
+    type Output = FloatExp;
+
+    fn rem(self, other: FloatExp) -> FloatExp {
+        self - (self / other).trunc() * other
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = FloatExp;
+
+    fn neg(self) -> FloatExp {
+        FloatExp {
+            mantissa: -self.mantissa,
+            ..self
+        }
+    }
+}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &FloatExp) -> Option<Ordering> {
+        let (a, b) = (self.mantissa, other.mantissa);
+
+        // Values of different sign and zero are ordered by their mantissa alone, values of
+        // the same sign by their exponent first
+        if a <= 0.0 && b >= 0.0 || a >= 0.0 && b <= 0.0 || self.exponent == other.exponent {
+            a.partial_cmp(&b)
+        } else if a > 0.0 {
+            self.exponent.partial_cmp(&other.exponent)
+        } else {
+            other.exponent.partial_cmp(&self.exponent)
+        }
+    }
+}
+
+impl Zero for FloatExp {
+    fn zero() -> Self {
+        FloatExp::default()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.mantissa == 0.0
+    }
+}
+
+impl One for FloatExp {
+    fn one() -> Self {
+        FloatExp::from_f64(1.0)
+    }
+}
+
+impl Num for FloatExp {
+    type FromStrRadixErr = <f64 as Num>::FromStrRadixErr;
+
+    fn from_str_radix(s: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
+        f64::from_str_radix(s, radix).map(FloatExp::from_f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f64_round_trip() {
+        for &value in &[
+            0.0,
+            1.0,
+            -1.0,
+            0.1,
+            -3.5e100,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            // Subnormal
+            f64::MIN_POSITIVE / 1024.0,
+            -5e-324,
+        ] {
+            assert_eq!(FloatExp::from_f64(value).to_f64(), value);
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        for &value in &[1.0, -1.0, 0.1, 12345.678, -5e-324] {
+            let value = FloatExp::from_f64(value);
+            assert!((0.5..1.0).contains(&value.mantissa().abs()));
+        }
+
+        assert_eq!(FloatExp::new(3.0, 10), FloatExp::new(0.75, 12));
+        assert_eq!(FloatExp::new(0.0, 10), FloatExp::from_f64(0.0));
+        assert_eq!(FloatExp::from_f64(0.0).exponent(), 0);
+    }
+
+    #[test]
+    fn out_of_f64_range() {
+        let huge = FloatExp::new(0.75, 5000);
+        let tiny = FloatExp::new(0.75, -5000);
+
+        assert_eq!(huge.to_f64(), f64::INFINITY);
+        assert_eq!((-huge).to_f64(), f64::NEG_INFINITY);
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert!(huge.is_finite());
+        assert_eq!(huge.log2(), 5000.0 + 0.75f64.log2());
+
+        // Arithmetic keeps working beyond the `f64` range
+        assert_eq!(huge * tiny, FloatExp::from_f64(0.5625));
+        assert_eq!(huge / huge, FloatExp::from_f64(1.0));
+        assert_eq!(tiny * tiny, FloatExp::new(0.5625, -10000));
+        assert_eq!((tiny * FloatExp::new(1.0, 4990)).to_f64(), 0.75 / 1024.0);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = FloatExp::from_f64(1.5);
+        let b = FloatExp::from_f64(-2.25);
+
+        assert_eq!((a + b).to_f64(), -0.75);
+        assert_eq!((a - b).to_f64(), 3.75);
+        assert_eq!((a * b).to_f64(), -3.375);
+        assert_eq!((b / a).to_f64(), -1.5);
+        assert_eq!(
+            (FloatExp::from_f64(7.5) % FloatExp::from_f64(2.0)).to_f64(),
+            1.5
+        );
+        assert_eq!(FloatExp::from_f64(-7.5).trunc().to_f64(), -7.0);
+        assert_eq!(FloatExp::from_f64(0.5).trunc(), FloatExp::zero());
+
+        // Values that are too small to change the sum are dropped
+        let tiny = FloatExp::new(1.0, -100);
+        assert_eq!(a + tiny, a);
+        assert_eq!(tiny + tiny, FloatExp::new(1.0, -99));
+    }
+
+    #[test]
+    fn ordering() {
+        let values = [
+            FloatExp::new(-0.5, 100),
+            FloatExp::from_f64(-1.0),
+            FloatExp::new(-0.5, -100),
+            FloatExp::from_f64(0.0),
+            FloatExp::new(0.5, -100),
+            FloatExp::from_f64(1.0),
+            FloatExp::new(0.5, 100),
+        ];
+
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(a.partial_cmp(b), i.partial_cmp(&j), "{a:?} vs. {b:?}");
+            }
+        }
+        assert_eq!(
+            FloatExp::new(0.5, -100).min(FloatExp::new(0.5, -200)),
+            FloatExp::new(0.5, -200)
+        );
+    }
+}
@@ -0,0 +1,1208 @@
+use gtk::glib;
+
+use num_complex::Complex64;
+
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Iteration typed in by the user, with the condition under which orbits escape
+///
+/// Both are expressions of the current value of the orbit `z` and the point `c`, with the
+/// usual arithmetic operators, `^` for powers, imaginary numbers like `2.5i`, the constants
+/// `i`, `pi` and `e`, and the functions of `Function`. The condition compares real numbers
+/// with `<`, `<=`, `>` and `>=` and combines comparisons with `&&` and `||`.
+#[derive(Clone, Debug, glib::Boxed)]
+#[boxed_type(name = "MandelbrotFormula")]
+pub struct Formula(Arc<Compiled>);
+
+#[derive(Debug)]
+struct Compiled {
+    iteration_source: String,
+    bailout_source: String,
+    iteration: Program,
+    bailout: Program,
+    degree: f64,
+}
+
+/// Part of a formula that an error was found in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormulaPart {
+    Iteration,
+    Bailout,
+}
+
+/// Error in the text of a formula
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormulaError {
+    pub part: FormulaPart,
+    pub error: ParseError,
+}
+
+/// Error in the text of an expression or a list of constants
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Byte range of the text that the error is about
+    pub span: Range<usize>,
+    /// Column at which the error starts, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+/// Maximum number of intermediate values while evaluating an expression
+const MAX_DEPTH: usize = 16;
+
+/// Maximum number of operations, parentheses and function calls nested in each other
+///
+/// Parsing and compiling recurse into nested expressions, this keeps them from overflowing
+/// the stack.
+const MAX_NESTING: usize = 64;
+
+/// Expression compiled to instructions for a stack machine
+#[derive(Debug)]
+struct Program {
+    ops: Vec<Op>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Constant(Complex64),
+    Z,
+    C,
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    /// Power with an integer exponent, which is faster and exact
+    PowerInteger(i32),
+    Call(Function),
+    Compare(Comparison),
+    And,
+    Or,
+}
+
+/// Function that can be called in expressions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    /// Natural logarithm
+    Log,
+    Sqrt,
+    /// Magnitude
+    Abs,
+    /// Squared magnitude
+    Norm,
+    /// Angle in radians
+    Arg,
+    Re,
+    Im,
+    Conj,
+}
+
+/// Comparison of the real parts of two values
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Formula {
+    /// Parses and compiles the `iteration` that gives the next value of an orbit, and the
+    /// `bailout` condition that is true once the orbit escaped
+    pub fn new(iteration: &str, bailout: &str) -> Result<Self, FormulaError> {
+        let parse = |source: &str, part, condition| {
+            Parser::new(source)
+                .and_then(|parser| parser.parse(condition))
+                .and_then(|expression| {
+                    let program = Program::compile(&expression).ok_or_else(|| {
+                        ParseError::new(
+                            source,
+                            expression.span.clone(),
+                            "Expression is nested too deeply",
+                        )
+                    })?;
+                    Ok((expression, program))
+                })
+                .map_err(|error| FormulaError { part, error })
+        };
+        let (expression, iteration_program) = parse(iteration, FormulaPart::Iteration, false)?;
+        let (_, bailout_program) = parse(bailout, FormulaPart::Bailout, true)?;
+
+        // Orbits of iterations without a polynomial degree above one don't escape at a known
+        // rate, color them as if they were squared like in the Mandelbrot set
+        let degree = expression.degree().filter(|&degree| degree > 1.0);
+
+        Ok(Formula(Arc::new(Compiled {
+            iteration_source: iteration.to_string(),
+            bailout_source: bailout.to_string(),
+            iteration: iteration_program,
+            bailout: bailout_program,
+            degree: degree.unwrap_or(2.0),
+        })))
+    }
+
+    pub fn iteration(&self) -> &str {
+        &self.0.iteration_source
+    }
+
+    pub fn bailout(&self) -> &str {
+        &self.0.bailout_source
+    }
+
+    /// Polynomial degree of the iteration in `z` if it has one, otherwise two
+    pub fn degree(&self) -> f64 {
+        self.0.degree
+    }
+
+    /// Next value of the orbit after `z`
+    #[inline]
+    pub fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        self.0.iteration.run(z, c)
+    }
+
+    /// Derivative of the value of the orbit after `z` from the derivative `dz` of `z` and the
+    /// derivative `dc` of `c`
+    #[inline]
+    pub fn step_derivative(
+        &self,
+        z: Complex64,
+        dz: Complex64,
+        c: Complex64,
+        dc: Complex64,
+    ) -> Complex64 {
+        self.0.iteration.run_derivative(z, dz, c, dc)
+    }
+
+    /// Whether the orbit escaped at `z`
+    #[inline]
+    pub fn escaped(&self, z: Complex64, c: Complex64) -> bool {
+        self.0.bailout.run(z, c).re != 0.0
+    }
+}
+
+impl Default for Formula {
+    fn default() -> Self {
+        Formula::new("z^2 + c", "abs(z) > 256").expect("default formula is valid")
+    }
+}
+
+impl PartialEq for Formula {
+    fn eq(&self, other: &Self) -> bool {
+        self.iteration() == other.iteration() && self.bailout() == other.bailout()
+    }
+}
+
+/// Parses a comma-separated list of constant expressions like `1, -0.5 + 0.866i`, with the
+/// text of each
+pub fn parse_constants(source: &str) -> Result<Vec<(Complex64, Range<usize>)>, ParseError> {
+    Parser::new(source)?.parse_constants()
+}
+
+impl ParseError {
+    pub(super) fn new(source: &str, span: Range<usize>, message: impl Into<String>) -> Self {
+        ParseError {
+            column: source[..span.start].chars().count() + 1,
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let part = match self.part {
+            FormulaPart::Iteration => "Formula",
+            FormulaPart::Bailout => "Bailout",
+        };
+        write!(
+            f,
+            "{part}, column {}: {}",
+            self.error.column, self.error.message
+        )
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "sinh" => Function::Sinh,
+            "cosh" => Function::Cosh,
+            "tanh" => Function::Tanh,
+            "exp" => Function::Exp,
+            "log" | "ln" => Function::Log,
+            "sqrt" => Function::Sqrt,
+            "abs" => Function::Abs,
+            "norm" => Function::Norm,
+            "arg" => Function::Arg,
+            "re" => Function::Re,
+            "im" => Function::Im,
+            "conj" => Function::Conj,
+            _ => return None,
+        })
+    }
+
+    #[inline]
+    fn apply(self, v: Complex64) -> Complex64 {
+        match self {
+            Function::Sin => v.sin(),
+            Function::Cos => v.cos(),
+            Function::Tan => v.tan(),
+            Function::Sinh => v.sinh(),
+            Function::Cosh => v.cosh(),
+            Function::Tanh => v.tanh(),
+            Function::Exp => v.exp(),
+            Function::Log => v.ln(),
+            Function::Sqrt => v.sqrt(),
+            Function::Abs => Complex64::new(v.norm(), 0.0),
+            Function::Norm => Complex64::new(v.norm_sqr(), 0.0),
+            Function::Arg => Complex64::new(v.arg(), 0.0),
+            Function::Re => Complex64::new(v.re, 0.0),
+            Function::Im => Complex64::new(v.im, 0.0),
+            Function::Conj => v.conj(),
+        }
+    }
+
+    /// Derivative of the result along the derivative `dv` of the argument `v`
+    ///
+    /// This is exact also for the functions that are not holomorphic, as long as only a
+    /// single direction is tracked.
+    #[inline]
+    fn derivative(self, v: Complex64, dv: Complex64) -> Complex64 {
+        match self {
+            Function::Sin => v.cos() * dv,
+            Function::Cos => -v.sin() * dv,
+            Function::Tan => dv / (v.cos() * v.cos()),
+            Function::Sinh => v.cosh() * dv,
+            Function::Cosh => v.sinh() * dv,
+            Function::Tanh => dv * (1.0 - v.tanh() * v.tanh()),
+            Function::Exp => v.exp() * dv,
+            Function::Log => dv / v,
+            Function::Sqrt => dv / (2.0 * v.sqrt()),
+            Function::Abs => Complex64::new((v.conj() * dv).re / v.norm(), 0.0),
+            Function::Norm => Complex64::new(2.0 * (v.conj() * dv).re, 0.0),
+            Function::Arg => Complex64::new((dv / v).im, 0.0),
+            Function::Re => Complex64::new(dv.re, 0.0),
+            Function::Im => Complex64::new(dv.im, 0.0),
+            Function::Conj => dv.conj(),
+        }
+    }
+
+    /// Whether the result has the same polynomial degree as the argument
+    fn keeps_degree(self) -> bool {
+        matches!(
+            self,
+            Function::Abs | Function::Re | Function::Im | Function::Conj
+        )
+    }
+}
+
+impl Comparison {
+    #[inline]
+    fn apply(self, a: f64, b: f64) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterEqual => a >= b,
+        }
+    }
+}
+
+fn truth(value: bool) -> Complex64 {
+    Complex64::new(if value { 1.0 } else { 0.0 }, 0.0)
+}
+
+impl Op {
+    /// Number of values the operation takes from the stack
+    fn arguments(self) -> usize {
+        match self {
+            Op::Constant(_) | Op::Z | Op::C => 0,
+            Op::Negate | Op::PowerInteger(_) | Op::Call(_) => 1,
+            _ => 2,
+        }
+    }
+
+    /// Result of the operation on `a` and `b`, where `b` is only used by operations with two
+    /// arguments and `a` only by those with at least one
+    #[inline]
+    fn apply(self, a: Complex64, b: Complex64, z: Complex64, c: Complex64) -> Complex64 {
+        match self {
+            Op::Constant(value) => value,
+            Op::Z => z,
+            Op::C => c,
+            Op::Negate => -a,
+            Op::Add => a + b,
+            Op::Subtract => a - b,
+            Op::Multiply => a * b,
+            Op::Divide => a / b,
+            Op::Power if b.im == 0.0 => a.powf(b.re),
+            Op::Power => a.powc(b),
+            Op::PowerInteger(exponent) => a.powi(exponent),
+            Op::Call(function) => function.apply(a),
+            Op::Compare(comparison) => truth(comparison.apply(a.re, b.re)),
+            Op::And => truth(a.re != 0.0 && b.re != 0.0),
+            Op::Or => truth(a.re != 0.0 || b.re != 0.0),
+        }
+    }
+
+    /// Derivative of the result of `apply` from the derivatives `da` and `db` of `a` and `b`
+    #[inline]
+    fn derivative(
+        self,
+        (a, da): (Complex64, Complex64),
+        (b, db): (Complex64, Complex64),
+        result: Complex64,
+        dz: Complex64,
+        dc: Complex64,
+    ) -> Complex64 {
+        let zero = Complex64::new(0.0, 0.0);
+        match self {
+            Op::Constant(_) => zero,
+            Op::Z => dz,
+            Op::C => dc,
+            Op::Negate => -da,
+            Op::Add => da + db,
+            Op::Subtract => da - db,
+            Op::Multiply => da * b + a * db,
+            Op::Divide => (da * b - a * db) / (b * b),
+            Op::PowerInteger(exponent) => exponent as f64 * a.powi(exponent - 1) * da,
+            // d(a^b) = a^b (b' ln a + b a' / a)
+            Op::Power => result * (db * a.ln() + b * da / a),
+            Op::Call(function) => function.derivative(a, da),
+            Op::Compare(_) | Op::And | Op::Or => zero,
+        }
+    }
+}
+
+impl Program {
+    /// Returns `None` if evaluating the expression needs more than `MAX_DEPTH` values
+    fn compile(expression: &Expression) -> Option<Self> {
+        let mut ops = Vec::new();
+        expression.compile(&mut ops);
+
+        let mut depth = 0usize;
+        for op in &ops {
+            depth = depth - op.arguments() + 1;
+            if depth > MAX_DEPTH {
+                return None;
+            }
+        }
+
+        Some(Program { ops })
+    }
+
+    #[inline]
+    fn run(&self, z: Complex64, c: Complex64) -> Complex64 {
+        let zero = Complex64::new(0.0, 0.0);
+        let mut stack = [zero; MAX_DEPTH];
+        let mut top = 0;
+
+        for &op in &self.ops {
+            let arguments = op.arguments();
+            top -= arguments;
+            let (a, b) = match arguments {
+                0 => (zero, zero),
+                1 => (stack[top], zero),
+                _ => (stack[top], stack[top + 1]),
+            };
+            stack[top] = op.apply(a, b, z, c);
+            top += 1;
+        }
+
+        stack[0]
+    }
+
+    /// Like `run` but returns the derivative of the result along the derivatives `dz` of `z`
+    /// and `dc` of `c`
+    #[inline]
+    fn run_derivative(
+        &self,
+        z: Complex64,
+        dz: Complex64,
+        c: Complex64,
+        dc: Complex64,
+    ) -> Complex64 {
+        let zero = (Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0));
+        let mut stack = [zero; MAX_DEPTH];
+        let mut top = 0;
+
+        for &op in &self.ops {
+            let arguments = op.arguments();
+            top -= arguments;
+            let (a, b) = match arguments {
+                0 => (zero, zero),
+                1 => (stack[top], zero),
+                _ => (stack[top], stack[top + 1]),
+            };
+            let result = op.apply(a.0, b.0, z, c);
+            stack[top] = (result, op.derivative(a, b, result, dz, dc));
+            top += 1;
+        }
+
+        stack[0].1
+    }
+}
+
+/// Parsed expression with the text it came from
+#[derive(Debug)]
+struct Expression {
+    kind: ExpressionKind,
+    /// Byte range in the source text
+    span: Range<usize>,
+    /// Number of operations nested in each other, including this one
+    height: usize,
+}
+
+#[derive(Debug)]
+enum ExpressionKind {
+    Number(f64),
+    /// Imaginary number, `i` itself has the value one
+    Imaginary(f64),
+    Z,
+    C,
+    Negate(Box<Expression>),
+    /// Binary arithmetic, comparison or logical operation
+    Binary(Op, Box<Expression>, Box<Expression>),
+    Call(Function, Box<Expression>),
+}
+
+impl Expression {
+    /// Whether this is a condition instead of a number
+    fn is_condition(&self) -> bool {
+        matches!(
+            self.kind,
+            ExpressionKind::Binary(Op::Compare(_) | Op::And | Op::Or, ..)
+        )
+    }
+
+    /// Appends the operations that evaluate this expression, with constant parts already
+    /// evaluated
+    fn compile(&self, ops: &mut Vec<Op>) {
+        let op = match &self.kind {
+            ExpressionKind::Number(value) => Op::Constant(Complex64::new(*value, 0.0)),
+            ExpressionKind::Imaginary(value) => Op::Constant(Complex64::new(0.0, *value)),
+            ExpressionKind::Z => Op::Z,
+            ExpressionKind::C => Op::C,
+            ExpressionKind::Negate(operand) => {
+                operand.compile(ops);
+                Op::Negate
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                left.compile(ops);
+                right.compile(ops);
+                match (*op, ops.last()) {
+                    (Op::Power, Some(&Op::Constant(exponent)))
+                        if exponent.im == 0.0
+                            && exponent.re.fract() == 0.0
+                            && exponent.re.abs() <= i32::MAX as f64 =>
+                    {
+                        ops.pop();
+                        Op::PowerInteger(exponent.re as i32)
+                    }
+                    (op, _) => op,
+                }
+            }
+            ExpressionKind::Call(function, argument) => {
+                argument.compile(ops);
+                Op::Call(*function)
+            }
+        };
+
+        // Fold operations on constants into a single constant
+        let arguments = op.arguments();
+        let constants = ops[ops.len() - arguments..]
+            .iter()
+            .map(|op| match op {
+                Op::Constant(value) => Some(*value),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        match constants {
+            Some(constants) if !matches!(op, Op::Z | Op::C) => {
+                ops.truncate(ops.len() - arguments);
+                let zero = Complex64::new(0.0, 0.0);
+                let a = constants.first().copied().unwrap_or(zero);
+                let b = constants.get(1).copied().unwrap_or(zero);
+                ops.push(Op::Constant(op.apply(a, b, zero, zero)));
+            }
+            _ => ops.push(op),
+        }
+    }
+
+    /// Polynomial degree in `z`, or `None` if this is not a polynomial in `z`
+    fn degree(&self) -> Option<f64> {
+        match &self.kind {
+            ExpressionKind::Number(_) | ExpressionKind::Imaginary(_) | ExpressionKind::C => {
+                Some(0.0)
+            }
+            ExpressionKind::Z => Some(1.0),
+            ExpressionKind::Negate(operand) => operand.degree(),
+            ExpressionKind::Binary(Op::Power, base, exponent) => match base.degree()? {
+                0.0 => exponent.degree().filter(|&degree| degree == 0.0),
+                base => Some(base * exponent.constant()?),
+            },
+            ExpressionKind::Binary(op, left, right) => {
+                let (left, right) = (left.degree()?, right.degree()?);
+                match op {
+                    Op::Add | Op::Subtract => Some(f64::max(left, right)),
+                    Op::Multiply => Some(left + right),
+                    Op::Divide if right == 0.0 => Some(left),
+                    _ => None,
+                }
+            }
+            ExpressionKind::Call(function, argument) => {
+                let degree = argument.degree()?;
+                (degree == 0.0 || function.keeps_degree()).then_some(degree)
+            }
+        }
+    }
+
+    /// Value of a real constant
+    fn constant(&self) -> Option<f64> {
+        match &self.kind {
+            ExpressionKind::Number(value) => Some(*value),
+            ExpressionKind::Negate(operand) => operand.constant().map(|value| -value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    /// Number directly followed by `i`
+    Imaginary(f64),
+    Identifier(String),
+    /// Operator or parenthesis
+    Symbol(&'static str),
+    End,
+}
+
+/// Recursive descent parser for expressions
+///
+/// From lowest to highest precedence, the grammar is:
+///
+/// ```text
+/// or         = and { "||" and }
+/// and        = comparison { "&&" comparison }
+/// comparison = sum [ ( "<" | "<=" | ">" | ">=" ) sum ]
+/// sum        = product { ( "+" | "-" ) product }
+/// product    = unary { ( "*" | "/" ) unary }
+/// unary      = "-" unary | power
+/// power      = atom [ "^" unary ]
+/// atom       = number | identifier | identifier "(" or ")" | "(" or ")"
+/// ```
+///
+/// Lists of constants are `or { "," or }`.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, Range<usize>)>,
+    position: usize,
+    /// Number of parentheses, function calls, negations and exponents around the current
+    /// token
+    depth: usize,
+}
+
+const SYMBOLS: &[&str] = &[
+    "||", "&&", "<=", ">=", "<", ">", "+", "-", "*", "/", "^", "(", ")", ",",
+];
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Result<Self, ParseError> {
+        let mut tokens = Vec::new();
+        let mut rest = source.char_indices().peekable();
+
+        while let Some(&(start, character)) = rest.peek() {
+            if character.is_whitespace() {
+                rest.next();
+                continue;
+            }
+
+            let token_end = |rest: &mut std::iter::Peekable<std::str::CharIndices>,
+                             accept: &dyn Fn(char, char) -> bool| {
+                let mut previous = ' ';
+                while let Some(&(_, character)) = rest.peek() {
+                    if !accept(previous, character) {
+                        break;
+                    }
+                    previous = character;
+                    rest.next();
+                }
+                rest.peek().map_or(source.len(), |&(end, _)| end)
+            };
+
+            if character.is_ascii_digit() || character == '.' {
+                // Digits with an optional fraction and exponent like `1.5e-3`
+                let end = token_end(&mut rest, &|previous, character| {
+                    character.is_ascii_digit()
+                        || character == '.'
+                        || character == 'e'
+                        || ((character == '-' || character == '+') && previous == 'e')
+                });
+                let text = &source[start..end];
+                let value = text.parse::<f64>().map_err(|_| {
+                    ParseError::new(source, start..end, format!("Invalid number `{text}`"))
+                })?;
+
+                // An `i` right after the number that doesn't start a longer name makes it
+                // imaginary
+                let mut after = source[end..].chars();
+                if after.next() == Some('i')
+                    && !after
+                        .next()
+                        .is_some_and(|character| character.is_alphanumeric() || character == '_')
+                {
+                    rest.next();
+                    tokens.push((Token::Imaginary(value), start..end + 1));
+                } else {
+                    tokens.push((Token::Number(value), start..end));
+                }
+            } else if character.is_alphabetic() || character == '_' {
+                let end = token_end(&mut rest, &|_, character| {
+                    character.is_alphabetic() || character.is_ascii_digit() || character == '_'
+                });
+                tokens.push((
+                    Token::Identifier(source[start..end].to_string()),
+                    start..end,
+                ));
+            } else if let Some(symbol) = SYMBOLS
+                .iter()
+                .find(|symbol| source[start..].starts_with(**symbol))
+            {
+                for _ in 0..symbol.len() {
+                    rest.next();
+                }
+                tokens.push((Token::Symbol(symbol), start..start + symbol.len()));
+            } else {
+                let end = start + character.len_utf8();
+                return Err(ParseError::new(
+                    source,
+                    start..end,
+                    format!("Unexpected character `{character}`"),
+                ));
+            }
+        }
+        tokens.push((Token::End, source.len()..source.len()));
+
+        Ok(Parser {
+            source,
+            tokens,
+            position: 0,
+            depth: 0,
+        })
+    }
+
+    /// Parses the whole text, which has to be a `condition` or a number
+    fn parse(mut self, condition: bool) -> Result<Expression, ParseError> {
+        let expression = self.or()?;
+        let (token, span) = self.peek();
+        if *token != Token::End {
+            let span = span.clone();
+            return Err(self.error(span, "Expected an operator"));
+        }
+
+        if condition && !expression.is_condition() {
+            Err(self.error(expression.span, "Expected a condition like `abs(z) > 2`"))
+        } else if !condition && expression.is_condition() {
+            Err(self.error(expression.span, "Expected a number, not a condition"))
+        } else {
+            Ok(expression)
+        }
+    }
+
+    /// Parses the whole text as a comma-separated list of numbers that depend on neither `z`
+    /// nor `c`
+    fn parse_constants(mut self) -> Result<Vec<(Complex64, Range<usize>)>, ParseError> {
+        let mut values = Vec::new();
+        loop {
+            let expression = self.or()?;
+            if expression.is_condition() {
+                return Err(self.error(expression.span, "Expected a number, not a condition"));
+            }
+            let program = Program::compile(&expression).ok_or_else(|| {
+                self.error(expression.span.clone(), "Expression is nested too deeply")
+            })?;
+            match program.ops[..] {
+                [Op::Constant(value)] => values.push((value, expression.span)),
+                _ => {
+                    return Err(self.error(
+                        expression.span,
+                        "Expected a constant that depends on neither `z` nor `c`",
+                    ))
+                }
+            }
+
+            if self.symbol(&[","]).is_none() {
+                break;
+            }
+        }
+
+        let (token, span) = self.peek();
+        if *token != Token::End {
+            let span = span.clone();
+            return Err(self.error(span, "Expected `,` or an operator"));
+        }
+
+        Ok(values)
+    }
+
+    fn error(&self, span: Range<usize>, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.source, span, message)
+    }
+
+    /// Expression of `kind` at `span`, if it is not nested too deeply
+    fn expression(
+        &self,
+        kind: ExpressionKind,
+        span: Range<usize>,
+    ) -> Result<Expression, ParseError> {
+        let height = match &kind {
+            ExpressionKind::Negate(operand) | ExpressionKind::Call(_, operand) => {
+                operand.height + 1
+            }
+            ExpressionKind::Binary(_, left, right) => usize::max(left.height, right.height) + 1,
+            _ => 1,
+        };
+        if height > MAX_NESTING {
+            return Err(self.error(span, "Expression is nested too deeply"));
+        }
+
+        Ok(Expression { kind, span, height })
+    }
+
+    fn peek(&self) -> &(Token, Range<usize>) {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> (Token, Range<usize>) {
+        let token = self.tokens[self.position].clone();
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    /// Consumes the next token if it is one of the `symbols`
+    fn symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek().0 {
+            Token::Symbol(symbol) if symbols.contains(&symbol) => {
+                self.next();
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    /// Combines `left` and `right` with `op`, which takes operands that are conditions if
+    /// `conditions` and otherwise numbers
+    fn binary(
+        &self,
+        op: Op,
+        left: Expression,
+        right: Expression,
+        conditions: bool,
+    ) -> Result<Expression, ParseError> {
+        for operand in [&left, &right] {
+            if operand.is_condition() != conditions {
+                let message = if conditions {
+                    "Expected a condition"
+                } else {
+                    "Expected a number, not a condition"
+                };
+                return Err(self.error(operand.span.clone(), message));
+            }
+        }
+
+        let span = left.span.start..right.span.end;
+        self.expression(
+            ExpressionKind::Binary(op, Box::new(left), Box::new(right)),
+            span,
+        )
+    }
+
+    fn or(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.and()?;
+        while self.symbol(&["||"]).is_some() {
+            let right = self.and()?;
+            left = self.binary(Op::Or, left, right, true)?;
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.comparison()?;
+        while self.symbol(&["&&"]).is_some() {
+            let right = self.comparison()?;
+            left = self.binary(Op::And, left, right, true)?;
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ParseError> {
+        let left = self.sum()?;
+        let comparison = match self.symbol(&["<", "<=", ">", ">="]) {
+            Some("<") => Comparison::Less,
+            Some("<=") => Comparison::LessEqual,
+            Some(">") => Comparison::Greater,
+            Some(">=") => Comparison::GreaterEqual,
+            _ => return Ok(left),
+        };
+        let right = self.sum()?;
+        self.binary(Op::Compare(comparison), left, right, false)
+    }
+
+    fn sum(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.product()?;
+        while let Some(symbol) = self.symbol(&["+", "-"]) {
+            let right = self.product()?;
+            let op = if symbol == "+" { Op::Add } else { Op::Subtract };
+            left = self.binary(op, left, right, false)?;
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.unary()?;
+        while let Some(symbol) = self.symbol(&["*", "/"]) {
+            let right = self.unary()?;
+            let op = if symbol == "*" {
+                Op::Multiply
+            } else {
+                Op::Divide
+            };
+            left = self.binary(op, left, right, false)?;
+        }
+        Ok(left)
+    }
+
+    /// Every nested expression is parsed from here, which is where the nesting is limited
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        let span = self.peek().1.clone();
+        if self.depth == MAX_NESTING {
+            return Err(self.error(span, "Expression is nested too deeply"));
+        }
+
+        self.depth += 1;
+        let expression = if self.symbol(&["-"]).is_some() {
+            let operand = self.unary()?;
+            if operand.is_condition() {
+                return Err(self.error(operand.span, "Expected a number, not a condition"));
+            }
+            let span = span.start..operand.span.end;
+            self.expression(ExpressionKind::Negate(Box::new(operand)), span)?
+        } else {
+            self.power()?
+        };
+        self.depth -= 1;
+
+        Ok(expression)
+    }
+
+    fn power(&mut self) -> Result<Expression, ParseError> {
+        let base = self.atom()?;
+        if self.symbol(&["^"]).is_none() {
+            return Ok(base);
+        }
+
+        let exponent = self.unary()?;
+        self.binary(Op::Power, base, exponent, false)
+    }
+
+    fn atom(&mut self) -> Result<Expression, ParseError> {
+        let (token, span) = self.next();
+        let kind = match token {
+            Token::Number(value) => ExpressionKind::Number(value),
+            Token::Imaginary(value) => ExpressionKind::Imaginary(value),
+            Token::Symbol("(") => {
+                let inner = self.or()?;
+                self.close(&span)?;
+                return Ok(Expression {
+                    span: span.start..self.tokens[self.position - 1].1.end,
+                    ..inner
+                });
+            }
+            Token::Identifier(name) if self.peek().0 == Token::Symbol("(") => {
+                let function = Function::from_name(&name).ok_or_else(|| {
+                    self.error(span.clone(), format!("Unknown function `{name}`"))
+                })?;
+                let (_, open) = self.next();
+                let argument = self.or()?;
+                if argument.is_condition() {
+                    return Err(self.error(argument.span, "Expected a number, not a condition"));
+                }
+                self.close(&open)?;
+                let span = span.start..self.tokens[self.position - 1].1.end;
+                return self.expression(ExpressionKind::Call(function, Box::new(argument)), span);
+            }
+            Token::Identifier(name) => match name.as_str() {
+                "z" => ExpressionKind::Z,
+                "c" => ExpressionKind::C,
+                "i" => ExpressionKind::Imaginary(1.0),
+                "pi" => ExpressionKind::Number(std::f64::consts::PI),
+                "e" => ExpressionKind::Number(std::f64::consts::E),
+                _ if Function::from_name(&name).is_some() => {
+                    return Err(self.error(
+                        span,
+                        format!("Function `{name}` needs an argument in parentheses"),
+                    ));
+                }
+                _ => return Err(self.error(span, format!("Unknown variable `{name}`"))),
+            },
+            Token::End => return Err(self.error(span, "Expected an expression")),
+            Token::Symbol(symbol) => {
+                return Err(self.error(span, format!("Expected an expression, found `{symbol}`")));
+            }
+        };
+
+        self.expression(kind, span)
+    }
+
+    /// Consumes the parenthesis that closes the one at `open`
+    fn close(&mut self, open: &Range<usize>) -> Result<(), ParseError> {
+        if self.symbol(&[")"]).is_some() {
+            return Ok(());
+        }
+
+        let (token, span) = self.peek().clone();
+        let message = match token {
+            Token::End => "Missing `)` for this `(`".to_string(),
+            _ => "Expected `)`".to_string(),
+        };
+        let span = if token == Token::End {
+            open.clone()
+        } else {
+            span
+        };
+        Err(self.error(span, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Error message of the iteration `source`
+    fn error(source: &str) -> String {
+        match Formula::new(source, "abs(z) > 2") {
+            Err(error) => error.error.message,
+            Ok(formula) => panic!("Expected error, got {:?}", formula),
+        }
+    }
+
+    /// Part, column and message of the error in a formula
+    fn formula_error(iteration: &str, bailout: &str) -> (FormulaPart, usize, String) {
+        match Formula::new(iteration, bailout) {
+            Err(FormulaError { part, error }) => (part, error.column, error.message),
+            Ok(formula) => panic!("Expected error, got {:?}", formula),
+        }
+    }
+
+    #[test]
+    fn mandelbrot() {
+        let formula = Formula::default();
+        let fractal = super::super::Fractal::Mandelbrot;
+
+        for y in 0..32 {
+            for x in 0..64 {
+                let c = Complex64::new(x as f64 / 18.0 - 2.5, y as f64 / 25.0 - 1.25);
+                let (mut z, mut previous) = (Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0));
+                for _ in 0..200 {
+                    let next = formula.step(z, c);
+                    z = fractal.step(z, c, &mut previous);
+                    assert_eq!(
+                        (next.re.to_bits(), next.im.to_bits()),
+                        (z.re.to_bits(), z.im.to_bits())
+                    );
+                    assert_eq!(
+                        formula.escaped(z, c),
+                        fractal.escaped(z, c),
+                        "{} at {}",
+                        c,
+                        z
+                    );
+                    if fractal.escaped(z, c) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn derivatives() {
+        let (z, dz) = (Complex64::new(0.3, -0.7), Complex64::new(0.8, 0.5));
+        let (c, dc) = (Complex64::new(-0.4, 0.2), Complex64::new(1.0, -0.3));
+        let h = 1e-6;
+
+        for source in [
+            "z^2 + c",
+            "z^-3 + c",
+            "z^2.5 + c",
+            "z^c",
+            "(1 + i)^z",
+            "c / z + z / (c + 1)",
+            "sin(z) * c",
+            "exp(z^2) + c",
+        ] {
+            let formula = Formula::new(source, "abs(z) > 2").unwrap();
+            let expected = (formula.step(z + h * dz, c + h * dc)
+                - formula.step(z - h * dz, c - h * dc))
+                / (2.0 * h);
+            let derivative = formula.step_derivative(z, dz, c, dc);
+
+            assert!(
+                (derivative - expected).norm() < 1e-7 * expected.norm().max(1.0),
+                "{}: {} instead of {}",
+                source,
+                derivative,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn syntax() {
+        let (z, c) = (Complex64::new(2.0, 0.0), Complex64::new(0.5, -1.0));
+        for (source, expected) in [
+            // Exponents bind tighter than negation, also a negated one
+            ("z^-2", Complex64::new(0.25, 0.0)),
+            ("-z^2", Complex64::new(-4.0, 0.0)),
+            ("2^-1 * z", Complex64::new(1.0, 0.0)),
+            ("2i", Complex64::new(0.0, 2.0)),
+            ("2.5i * z + c", Complex64::new(0.5, 4.0)),
+            ("1.5e1i", Complex64::new(0.0, 15.0)),
+            ("i^2", Complex64::new(-1.0, 0.0)),
+        ] {
+            let formula = Formula::new(source, "abs(z) > 2").unwrap();
+            assert_eq!(formula.step(z, c), expected, "{}", source);
+        }
+
+        // An `i` that starts a name is not part of the number
+        assert_eq!(error("2im(z)"), "Expected an operator");
+        assert_eq!(error("2 i"), "Expected an operator");
+    }
+
+    #[test]
+    fn errors() {
+        use FormulaPart::*;
+
+        for ((iteration, bailout), expected) in [
+            (
+                ("z^2 + foo(c)", "abs(z) > 2"),
+                (Iteration, 7, "Unknown function `foo`"),
+            ),
+            (
+                ("sin(z + c", "abs(z) > 2"),
+                (Iteration, 4, "Missing `)` for this `(`"),
+            ),
+            (("sin(z c)", "abs(z) > 2"), (Iteration, 7, "Expected `)`")),
+            (
+                ("z^2 + c", "(abs(z) > 2"),
+                (Bailout, 1, "Missing `)` for this `(`"),
+            ),
+            // Columns count characters, not bytes
+            (
+                ("zé + c ²", "abs(z) > 2"),
+                (Iteration, 8, "Unexpected character `²`"),
+            ),
+            (
+                ("z^2 + c", "abs(z) > 2 $"),
+                (Bailout, 12, "Unexpected character `$`"),
+            ),
+            (
+                ("z + .", "abs(z) > 2"),
+                (Iteration, 5, "Invalid number `.`"),
+            ),
+            (
+                ("z^2 + 1e", "abs(z) > 2"),
+                (Iteration, 7, "Invalid number `1e`"),
+            ),
+            (
+                ("z^2 + c", "abs(z)"),
+                (Bailout, 1, "Expected a condition like `abs(z) > 2`"),
+            ),
+            (
+                ("z^2 + c", "re(z) + 2"),
+                (Bailout, 1, "Expected a condition like `abs(z) > 2`"),
+            ),
+            (
+                ("z^2 + c", "z > 2 && 1"),
+                (Bailout, 10, "Expected a condition"),
+            ),
+            (
+                ("z^2 + c > 2", "abs(z) > 2"),
+                (Iteration, 1, "Expected a number, not a condition"),
+            ),
+        ] {
+            let (part, column, message) = expected;
+            assert_eq!(
+                formula_error(iteration, bailout),
+                (part, column, message.to_string()),
+                "{} / {}",
+                iteration,
+                bailout
+            );
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |open: &str, close: &str, count: usize| {
+            format!("{}z{}", open.repeat(count), close.repeat(count))
+        };
+
+        assert!(Formula::new(&nested("(", ")", 32), "abs(z) > 2").is_ok());
+        assert!(Formula::new(&nested("sin(", ")", 32), "abs(z) > 2").is_ok());
+        assert!(Formula::new(&nested("z + ", "", 32), "abs(z) > 2").is_ok());
+
+        for source in [
+            nested("(", ")", 100_000),
+            nested("sin(", ")", 100_000),
+            nested("-", "", 100_000),
+            nested("z^", "", 100_000),
+            nested("z + ", "", 100_000),
+            nested("(z + ", ")", 100_000),
+        ] {
+            assert_eq!(error(&source), "Expression is nested too deeply");
+        }
+    }
+
+    #[test]
+    fn constants() {
+        let values = parse_constants("1, -0.5 + 0.866i, 2^3").unwrap();
+        assert_eq!(
+            values,
+            [
+                (Complex64::new(1.0, 0.0), 0..1),
+                (Complex64::new(-0.5, 0.866), 3..16),
+                (Complex64::new(8.0, 0.0), 18..21)
+            ]
+        );
+
+        let error = parse_constants("1, z").unwrap_err();
+        assert_eq!((error.span, error.column), (3..4, 4));
+    }
+}
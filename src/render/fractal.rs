@@ -0,0 +1,370 @@
+use gtk::glib;
+
+use num_complex::Complex64;
+
+use super::formula::Formula;
+use super::newton::Polynomial;
+use super::{View, BAILOUT};
+
+/// Family of escape-time fractals that can be rendered
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotFractalFamily")]
+pub enum FractalFamily {
+    #[enum_value(name = "Mandelbrot")]
+    Mandelbrot,
+    /// Mandelbrot set with a different exponent
+    #[enum_value(name = "Multibrot")]
+    Multibrot,
+    #[enum_value(name = "Burning Ship")]
+    BurningShip,
+    #[enum_value(name = "Tricorn")]
+    Tricorn,
+    #[enum_value(name = "Celtic")]
+    Celtic,
+    #[enum_value(name = "Buffalo")]
+    Buffalo,
+    #[enum_value(name = "Phoenix")]
+    Phoenix,
+    /// Iteration typed in by the user
+    #[enum_value(name = "Formula")]
+    Formula,
+    /// Basins of the roots of a polynomial under Newton's method
+    #[enum_value(name = "Newton")]
+    Newton,
+}
+
+/// Iteration of an escape-time fractal, starting from `Z(0) = 0` for every point `c`
+///
+/// Newton's method has no `c` and starts from every point instead, and its orbits converge to
+/// a root instead of escaping.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Fractal {
+    /// `Z(n+1) = Z(n)² + c`
+    #[default]
+    Mandelbrot,
+    /// `Z(n+1) = Z(n)^exponent + c`, with the principal branch for non-integer exponents
+    Multibrot { exponent: f64 },
+    /// `Z(n+1) = (|Re Z(n)| + i |Im Z(n)|)² + c`
+    BurningShip,
+    /// `Z(n+1) = conj(Z(n))² + c`, also known as Mandelbar
+    Tricorn,
+    /// `Z(n+1) = |Re Z(n)²| + i Im Z(n)² + c`
+    Celtic,
+    /// `Z(n+1) = |Re Z(n)²| + i |Im Z(n)²| + c`
+    Buffalo,
+    /// `Z(n+1) = Z(n)² + c + p Z(n-1)`
+    Phoenix { p: f64 },
+    /// Iteration and escape condition of a `Formula`
+    Formula(Formula),
+    /// `Z(n+1) = Z(n) - p(Z(n)) / p'(Z(n))`, until `Z(n)` is close to a root of `p`
+    Newton(Polynomial),
+}
+
+impl Fractal {
+    /// `exponent` is only used by the Multibrot, `p` only by the Phoenix, `formula` only by
+    /// the Formula and `polynomial` only by the Newton family
+    pub fn new(
+        family: FractalFamily,
+        exponent: f64,
+        p: f64,
+        formula: &Formula,
+        polynomial: &Polynomial,
+    ) -> Self {
+        match family {
+            FractalFamily::Mandelbrot => Fractal::Mandelbrot,
+            FractalFamily::Multibrot => Fractal::Multibrot { exponent },
+            FractalFamily::BurningShip => Fractal::BurningShip,
+            FractalFamily::Tricorn => Fractal::Tricorn,
+            FractalFamily::Celtic => Fractal::Celtic,
+            FractalFamily::Buffalo => Fractal::Buffalo,
+            FractalFamily::Phoenix => Fractal::Phoenix { p },
+            FractalFamily::Formula => Fractal::Formula(formula.clone()),
+            FractalFamily::Newton => Fractal::Newton(polynomial.clone()),
+        }
+    }
+
+    /// Smallest view that contains the whole set, or its whole Julia sets with `julia`
+    pub fn initial_view(&self, julia: bool) -> View {
+        match (self, julia) {
+            (Fractal::Newton(polynomial), _) => {
+                // The basins reach to infinity, show all roots with the area around them
+                let (center, radius) = polynomial.bounds();
+                let radius = radius.max(0.5);
+                View::new(center.re, center.im, 4.0 * radius, 3.0 * radius)
+            }
+            (Fractal::Multibrot { exponent }, _) => {
+                // The set and its connected Julia sets are inside the circle of this radius
+                let radius = f64::powf(2.0, 1.0 / (exponent - 1.0));
+                View::new(0.0, 0.0, 2.2 * radius, 2.2 * radius)
+            }
+            // The Julia sets of all other families are symmetric around the origin, and
+            // formulas could have any shape
+            (_, true) | (Fractal::Formula(_), _) => View::new(0.0, 0.0, 4.0, 3.0),
+            (Fractal::Mandelbrot | Fractal::Phoenix { .. }, false) => {
+                View::new(-0.75, 0.0, 3.5, 2.0)
+            }
+            (Fractal::BurningShip, false) => View::new(-0.5, -0.55, 3.5, 2.4),
+            (Fractal::Tricorn, false) => View::new(-0.55, 0.0, 3.3, 3.3),
+            (Fractal::Celtic, false) => View::new(-0.85, 0.0, 2.8, 3.4),
+            (Fractal::Buffalo, false) => View::new(-0.5, -0.6, 3.5, 2.5),
+        }
+    }
+
+    /// Growth of the magnitude of escaping orbits, `|Z(n+1)| ≈ |Z(n)|^degree`
+    pub fn degree(&self) -> f64 {
+        match self {
+            Fractal::Multibrot { exponent } => *exponent,
+            Fractal::Formula(formula) => formula.degree(),
+            _ => 2.0,
+        }
+    }
+
+    /// Whether an orbit that returns to one of its earlier values repeats from there on
+    ///
+    /// This is not the case if the next value depends on more than the last one.
+    pub fn is_memoryless(&self) -> bool {
+        !matches!(self, Fractal::Phoenix { .. })
+    }
+
+    /// Whether the orbit escaped at `z`, or converged to a root for Newton's method
+    #[inline]
+    pub fn escaped(&self, z: Complex64, c: Complex64) -> bool {
+        match self {
+            Fractal::Formula(formula) => formula.escaped(z, c),
+            Fractal::Newton(polynomial) => polynomial.root(z).is_some(),
+            _ => z.norm_sqr() >= BAILOUT,
+        }
+    }
+
+    /// Next value of the orbit after `z`
+    ///
+    /// `previous` is the value of the orbit before `z`, and is replaced by `z`.
+    #[inline]
+    pub fn step(&self, z: Complex64, c: Complex64, previous: &mut Complex64) -> Complex64 {
+        let next = match *self {
+            Fractal::Mandelbrot => z * z + c,
+            Fractal::Multibrot { exponent } => power(z, exponent) + c,
+            Fractal::BurningShip => {
+                let w = Complex64::new(z.re.abs(), z.im.abs());
+                w * w + c
+            }
+            Fractal::Tricorn => {
+                let w = z.conj();
+                w * w + c
+            }
+            Fractal::Celtic => {
+                let w = z * z;
+                Complex64::new(w.re.abs(), w.im) + c
+            }
+            Fractal::Buffalo => {
+                let w = z * z;
+                Complex64::new(w.re.abs(), w.im.abs()) + c
+            }
+            Fractal::Phoenix { p } => z * z + c + p * *previous,
+            Fractal::Formula(ref formula) => formula.step(z, c),
+            Fractal::Newton(ref polynomial) => polynomial.step(z),
+        };
+        *previous = z;
+
+        next
+    }
+
+    /// Derivative of the value of the orbit after `z` from the derivative `dz` of `z`
+    ///
+    /// `dc` is the derivative of `c` and `previous` the derivative before `dz`, which is
+    /// replaced by `dz`. The absolute values and conjugates of the other families than
+    /// Mandelbrot, Multibrot and Phoenix reflect the derivative, which makes it depend on its
+    /// direction. The one along `dc` is tracked, which is only an approximation for
+    /// estimating distances.
+    #[inline]
+    pub fn step_derivative(
+        &self,
+        z: Complex64,
+        dz: Complex64,
+        c: Complex64,
+        dc: f64,
+        previous: &mut Complex64,
+    ) -> Complex64 {
+        let next = match *self {
+            Fractal::Mandelbrot => 2.0 * z * dz + dc,
+            Fractal::Multibrot { exponent } => exponent * power(z, exponent - 1.0) * dz + dc,
+            Fractal::BurningShip => {
+                let w = Complex64::new(z.re.abs(), z.im.abs());
+                let dw = Complex64::new(z.re.signum() * dz.re, z.im.signum() * dz.im);
+                2.0 * w * dw + dc
+            }
+            Fractal::Tricorn => 2.0 * z.conj() * dz.conj() + dc,
+            Fractal::Celtic => {
+                let (w, dw) = (z * z, 2.0 * z * dz);
+                Complex64::new(w.re.signum() * dw.re, dw.im) + dc
+            }
+            Fractal::Buffalo => {
+                let (w, dw) = (z * z, 2.0 * z * dz);
+                Complex64::new(w.re.signum() * dw.re, w.im.signum() * dw.im) + dc
+            }
+            Fractal::Phoenix { p } => 2.0 * z * dz + dc + p * *previous,
+            Fractal::Formula(ref formula) => {
+                formula.step_derivative(z, dz, c, Complex64::new(dc, 0.0))
+            }
+            Fractal::Newton(ref polynomial) => polynomial.step_derivative(z, dz),
+        };
+        *previous = dz;
+
+        next
+    }
+}
+
+/// `z^exponent`, exactly for integer exponents
+#[inline]
+fn power(z: Complex64, exponent: f64) -> Complex64 {
+    if exponent.fract() == 0.0 {
+        z.powi(exponent as i32)
+    } else {
+        z.powf(exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All families apart from the Formula and Newton, with their default parameters and a
+    /// non-integer exponent
+    fn families() -> Vec<Fractal> {
+        vec![
+            Fractal::Mandelbrot,
+            Fractal::Multibrot { exponent: 3.0 },
+            Fractal::Multibrot { exponent: 2.5 },
+            Fractal::BurningShip,
+            Fractal::Tricorn,
+            Fractal::Celtic,
+            Fractal::Buffalo,
+            Fractal::Phoenix { p: -0.5 },
+        ]
+    }
+
+    #[test]
+    fn steps() {
+        let c = Complex64::new(0.5, 0.25);
+        // (1 + 2i)² = -3 + 4i, (-1 + 2i)² = -3 - 4i
+        for (fractal, z, expected) in [
+            (Fractal::BurningShip, (-1.0, -2.0), (-2.5, 4.25)),
+            (Fractal::BurningShip, (1.0, -2.0), (-2.5, 4.25)),
+            (Fractal::Tricorn, (-1.0, -2.0), (-2.5, -3.75)),
+            (Fractal::Tricorn, (1.0, -2.0), (-2.5, 4.25)),
+            (Fractal::Celtic, (1.0, 2.0), (3.5, 4.25)),
+            (Fractal::Celtic, (1.0, -2.0), (3.5, -3.75)),
+            (Fractal::Buffalo, (1.0, 2.0), (3.5, 4.25)),
+            (Fractal::Buffalo, (1.0, -2.0), (3.5, 4.25)),
+        ] {
+            let z = Complex64::new(z.0, z.1);
+            let mut previous = Complex64::new(0.0, 0.0);
+            assert_eq!(
+                fractal.step(z, c, &mut previous),
+                Complex64::new(expected.0, expected.1),
+                "{:?} at {}",
+                fractal,
+                z
+            );
+            assert_eq!(previous, z);
+        }
+    }
+
+    #[test]
+    fn phoenix() {
+        let fractal = Fractal::Phoenix { p: -0.5 };
+        let c = Complex64::new(0.5, 0.0);
+        let mut previous = Complex64::new(0.0, 0.0);
+        let mut z = Complex64::new(0.0, 0.0);
+
+        // 0.5, 0.25 + 0.5 + 0, 0.5625 + 0.5 - 0.25
+        for (expected, expected_previous) in [(0.5, 0.0), (0.75, 0.5), (0.8125, 0.75)] {
+            z = fractal.step(z, c, &mut previous);
+            assert_eq!(z, Complex64::new(expected, 0.0));
+            assert_eq!(previous, Complex64::new(expected_previous, 0.0));
+        }
+    }
+
+    #[test]
+    fn non_integer_exponent_at_zero() {
+        let fractal = Fractal::Multibrot { exponent: 2.5 };
+        let (z, c) = (Complex64::new(0.0, 0.0), Complex64::new(-0.5, 0.25));
+        let mut previous = Complex64::new(0.0, 0.0);
+
+        assert_eq!(fractal.step(z, c, &mut previous), c);
+        let dz = fractal.step_derivative(z, Complex64::new(1.0, 0.0), c, 1.0, &mut previous);
+        assert_eq!(dz, Complex64::new(1.0, 0.0));
+
+        // The lowest exponent has the most negative power in the derivative
+        let fractal = Fractal::Multibrot { exponent: 1.1 };
+        let next = fractal.step(z, c, &mut previous);
+        let dz = fractal.step_derivative(z, Complex64::new(1.0, 0.0), c, 1.0, &mut previous);
+        assert!(!next.is_nan() && !dz.is_nan(), "{} {}", next, dz);
+    }
+
+    #[test]
+    fn derivatives() {
+        // Away from the axes, where the absolute values have no kinks
+        let (z, dz) = (Complex64::new(0.3, -0.7), Complex64::new(0.8, 0.5));
+        let (previous, dprevious) = (Complex64::new(0.1, 0.2), Complex64::new(-0.3, 0.4));
+        let (c, dc) = (Complex64::new(-0.4, 0.2), 1.0);
+        let h = 1e-6;
+
+        for fractal in families() {
+            // Moves every value along its derivative by `t`
+            let next =
+                |t: f64| fractal.step(z + t * dz, c + t * dc, &mut (previous + t * dprevious));
+            let expected = (next(h) - next(-h)) / (2.0 * h);
+            let derivative = fractal.step_derivative(z, dz, c, dc, &mut dprevious.clone());
+
+            assert!(
+                (derivative - expected).norm() < 1e-8,
+                "{:?}: {} instead of {}",
+                fractal,
+                derivative,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn initial_views() {
+        for (fractal, points) in [
+            (
+                Fractal::Mandelbrot,
+                &[(0.0, 0.0), (-2.0, 0.0), (0.25, 0.0)][..],
+            ),
+            (
+                Fractal::Multibrot { exponent: 3.0 },
+                &[(0.0, 1.0), (0.0, -1.0)],
+            ),
+            (Fractal::Multibrot { exponent: 2.5 }, &[(0.0, 0.0)]),
+            (Fractal::BurningShip, &[(0.0, 0.0), (-2.0, 0.0)]),
+            (Fractal::Tricorn, &[(0.0, 0.0), (-2.0, 0.0)]),
+            (Fractal::Celtic, &[(0.0, 0.0), (-2.0, 0.0)]),
+            (Fractal::Buffalo, &[(0.0, 0.0), (-2.0, 0.0)]),
+            (Fractal::Phoenix { p: -0.5 }, &[(0.0, 0.0), (-1.0, 0.0)]),
+        ] {
+            let view = fractal.initial_view(false);
+            let (left, top) = view.point(0.0, 0.0);
+            let (right, bottom) = view.point(1.0, 1.0);
+
+            for &(x, y) in points {
+                let c = Complex64::new(x, y);
+                assert!(
+                    (left..=right).contains(&x) && (top..=bottom).contains(&y),
+                    "{:?}: {} outside of the view",
+                    fractal,
+                    c
+                );
+
+                let mut z = Complex64::new(0.0, 0.0);
+                let mut previous = Complex64::new(0.0, 0.0);
+                for _ in 0..1000 {
+                    z = fractal.step(z, c, &mut previous);
+                    assert!(!fractal.escaped(z, c), "{:?}: {} escaped", fractal, c);
+                }
+            }
+        }
+    }
+}
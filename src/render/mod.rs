@@ -0,0 +1,1413 @@
+use gtk::glib;
+
+use num_complex::Complex64;
+
+use rayon::prelude::*;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+mod bigfixed;
+mod color;
+mod density;
+mod floatexp;
+mod formula;
+mod fractal;
+mod newton;
+mod orbit;
+mod palette;
+mod perturbation;
+mod simd;
+#[doc(hidden)]
+pub mod support;
+
+pub use color::{ColorMapping, Coloring, DistanceEstimation, InteriorColoring, Interpolation};
+pub use density::RenderMode;
+pub use formula::{Formula, FormulaPart};
+pub use fractal::{Fractal, FractalFamily};
+pub use newton::{Polynomial, PolynomialInput};
+pub use orbit::ColoringAlgorithm;
+pub use palette::Palette;
+
+use bigfixed::BigFixed;
+use color::{Histogram, Pixel};
+use floatexp::FloatExp;
+use orbit::{Cycle, Tracking, TrackingOptions};
+use perturbation::Perturbation;
+
+/// Rendered part of a frame
+#[derive(Debug)]
+pub struct Tile {
+    pixels: Vec<Pixel>,
+    /// Position of the tile in the frame
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Factor by which this tile is downscaled from the requested size
+    pub scale: usize,
+    /// Frame this tile belongs to
+    pub frame: u64,
+    /// Iteration limit that was actually used for rendering
+    pub max_iterations: u32,
+}
+
+/// Position and size of a tile in the frame
+#[derive(Copy, Clone, Debug)]
+struct TileArea {
+    x: usize,
+    y: usize,
+    /// Size in pixels of the pass the tile belongs to
+    width: usize,
+    height: usize,
+    /// Factor by which the pass is downscaled from the requested size
+    scale: usize,
+}
+
+/// Escape statistics of a rendered frame
+#[derive(Clone, Debug, Default)]
+struct IterationStats {
+    /// Number of points that escaped
+    escaped: usize,
+    /// Number of points that escaped in the upper half of the iteration limit
+    escaped_late: usize,
+    /// Highest iteration count of any point that escaped
+    max_escaped: u32,
+    /// Number of points that did not escape before the iteration limit
+    interior: usize,
+    /// Number of points that escaped after every number of iterations, up to `max_escaped`
+    counts: Vec<usize>,
+}
+
+/// Result of iterating a single pixel, which is colored in a separate pass
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Sample {
+    /// Number of iterations until the point escaped, or the iteration limit
+    iterations: u32,
+    /// Magnitude of the last value of the orbit
+    magnitude: f32,
+    /// Fraction of the last iteration that was needed to escape, for smooth iteration counts
+    fraction: f32,
+    /// Whether the point did not escape before the iteration limit
+    interior: bool,
+    /// Estimated distance to the boundary of the set in pixels, from the outside for escaped
+    /// points and from the inside for points whose attracting cycle was found, or infinity if
+    /// it was not estimated
+    distance: f32,
+    /// Direction of the surface normal of escaped points in radians, or zero if the derivative
+    /// was not tracked
+    normal: f32,
+    /// Value of the orbit statistic, or zero if none was accumulated
+    statistic: f32,
+    /// Period of the attracting cycle of points that did not escape, or zero if it was not
+    /// found
+    period: u32,
+    /// Iteration at which the orbit came closest to zero, or zero if it was not tracked
+    atom_domain: u32,
+    /// Index of the root that Newton's method converged to plus one, or zero for other
+    /// fractals
+    root: u32,
+}
+
+/// Full resolution samples of a completely rendered frame
+#[derive(Debug)]
+struct Frame {
+    view: View,
+    width: usize,
+    height: usize,
+    max_iterations: u32,
+    /// Samples of every tile, in the same order as the tiles are rendered
+    tiles: Vec<Vec<Sample>>,
+    stats: IterationStats,
+    /// What was calculated for the samples in addition to the escape time
+    tracking: TrackingOptions,
+    /// Whether uniform rectangles were filled instead of calculating every sample
+    subdivided: bool,
+    fractal: Fractal,
+    /// Constant of the Julia set that was rendered instead of the Mandelbrot set, if any
+    julia: Option<Complex64>,
+}
+
+/// Region of the complex plane that is rendered
+///
+/// The center is stored with arbitrary precision so that zooming is not limited by the
+/// precision of `f64`. The precision grows automatically with the magnification. The size
+/// has an extended exponent as it becomes too small for `f64` after zooming in far enough.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    center_x: BigFixed,
+    center_y: BigFixed,
+    width: FloatExp,
+    height: FloatExp,
+}
+
+/// Flag for aborting a render that was superseded by a newer one
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+#[derive(Debug)]
+pub enum Command {
+    Render {
+        view: View,
+        target_width: usize,
+        target_height: usize,
+        max_iterations: u32,
+        iteration_mode: IterationMode,
+        /// Whether to fill uniform rectangles instead of calculating every pixel
+        subdivision: bool,
+        fractal: Fractal,
+        /// Constant `c` to render the Julia set of instead of the set of all `c`
+        julia: Option<Complex64>,
+        coloring: Coloring,
+        frame: u64,
+        cancel: CancellationToken,
+    },
+    /// Renders the density of escaping orbits with a `RenderMode` other than escape times,
+    /// which is refined until cancelled or until the next command
+    RenderDensity {
+        view: View,
+        target_width: usize,
+        target_height: usize,
+        max_iterations: u32,
+        mode: RenderMode,
+        fractal: Fractal,
+        julia: Option<Complex64>,
+        frame: u64,
+        cancel: CancellationToken,
+    },
+    /// Colors the last rendered frame again
+    Recolor {
+        coloring: Coloring,
+        frame: u64,
+    },
+    Quit,
+}
+
+/// How the iteration limit used for rendering is derived from the `max-iterations` property
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotIterationMode")]
+pub enum IterationMode {
+    /// Use `max-iterations` as is
+    #[enum_value(name = "Fixed")]
+    Fixed,
+    /// Scale `max-iterations` with the logarithm of the magnification
+    #[enum_value(name = "Log-scaled")]
+    LogScaled,
+    /// Adjust the limit based on the escape statistics of the previous frame
+    #[enum_value(name = "Adaptive")]
+    Adaptive,
+}
+
+pub const DEFAULT_MAX_ITERATIONS: u32 = 1000;
+pub const MAX_MAX_ITERATIONS: u32 = 1_000_000;
+
+// Size of the view that contains the whole Mandelbrot set, which magnifications are relative to
+const MIN_WIDTH: f64 = 3.5;
+const MIN_HEIGHT: f64 = 2.0;
+
+/// Squared magnitude after which a point is considered escaped
+const BAILOUT: f64 = (1 << 16) as f64;
+
+/// Size of the square tiles a frame is split into, in pixels of the full resolution pass
+///
+/// This has to be a multiple of the coarsest pass' downscaling factor.
+const TILE_SIZE: usize = 256;
+
+/// Downscaling factors of the progressive rendering passes, from coarse to fine
+///
+/// Each pass has twice the resolution of the previous one and only needs to calculate the
+/// pixels that are not shared with it.
+const PASSES: [usize; 4] = [8, 4, 2, 1];
+
+/// Pixel size below which `f64` is not precise enough anymore to iterate pixels directly
+const PERTURBATION_THRESHOLD: f64 = 1e-12;
+
+/// Pixel size below which the differences between pixels can't be represented by `f64`
+/// anymore when iterating relative to the reference orbit
+const DEEP_PERTURBATION_THRESHOLD: f64 = 1e-290;
+
+/// Squared distance between two values of an orbit below which it is considered periodic
+///
+/// Orbits that converge to an attracting cycle end up on exactly the same `f64` values, so
+/// this only has to allow for the last bits of rounding.
+const PERIODICITY_TOLERANCE: f64 = 1e-30;
+
+/// Size below which rectangles are calculated completely instead of being subdivided further
+const MIN_SUBDIVISION_SIZE: usize = 8;
+
+impl AsRef<[u8]> for Tile {
+    fn as_ref(&self) -> &[u8] {
+        use zerocopy::IntoBytes;
+        self.pixels.as_bytes()
+    }
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Tile {
+    fn new(
+        area: TileArea,
+        samples: &[Sample],
+        coloring: &Coloring,
+        histogram: Option<&Histogram>,
+        frame: u64,
+        max_iterations: u32,
+    ) -> Self {
+        Tile {
+            pixels: samples
+                .iter()
+                .map(|sample| coloring.color(sample, histogram))
+                .collect(),
+            x: area.x,
+            y: area.y,
+            width: area.width,
+            height: area.height,
+            scale: area.scale,
+            frame,
+            max_iterations,
+        }
+    }
+}
+
+impl Sample {
+    /// `tracking` contains the derivative of `z` with respect to `c` in units of the pixel size
+    /// and the statistic and atom domain of the orbit, if they were tracked. `cycle` is the
+    /// attracting cycle of points that did not escape, if it was searched for and found.
+    /// `degree` is the growth of escaping orbits per iteration, see `Fractal::degree`.
+    fn new(
+        iterations: u32,
+        z: Complex64,
+        tracking: Tracking<f64>,
+        cycle: Option<Cycle>,
+        max_iterations: u32,
+        degree: f64,
+    ) -> Self {
+        let magnitude = z.norm();
+        let nu = f64::ln(magnitude.ln() / f64::ln(2.0)) / degree.ln();
+        // Bailout conditions of formulas can stop orbits at magnitudes of two or less, or after
+        // they overflowed, where the fraction isn't defined. They needed the whole last iteration then.
+        let nu = if nu.is_finite() {
+            f64::max(nu, 0.0)
+        } else {
+            0.0
+        };
+        let (distance, normal) = match tracking.derivative {
+            Some(derivative) => (
+                magnitude * magnitude.ln() / derivative.norm(),
+                (z / derivative).arg(),
+            ),
+            None => (f64::INFINITY, 0.0),
+        };
+        let interior = iterations >= max_iterations;
+        let distance = match cycle {
+            Some(cycle) if interior => cycle.distance,
+            _ if interior => f64::INFINITY,
+            _ => distance,
+        };
+
+        Sample {
+            iterations,
+            magnitude: magnitude as f32,
+            fraction: (1.0 - nu) as f32,
+            interior,
+            distance: distance as f32,
+            normal: normal as f32,
+            statistic: tracking
+                .statistic
+                .map_or(0.0, |statistic| statistic.value(z) as f32),
+            period: cycle.map_or(0, |cycle| cycle.period),
+            atom_domain: tracking
+                .atom_domain
+                .map_or(0, |atom_domain| atom_domain.iteration),
+            root: 0,
+        }
+    }
+
+    /// Sample of Newton's method for `polynomial`, which converged to one of its roots if it
+    /// stopped before the iteration limit
+    ///
+    /// Nothing is tracked along these orbits.
+    fn newton(iterations: u32, z: Complex64, polynomial: &Polynomial, max_iterations: u32) -> Self {
+        let sample = Sample {
+            magnitude: z.norm() as f32,
+            distance: f32::INFINITY,
+            ..Sample::default()
+        };
+
+        match polynomial.root(z) {
+            Some((root, excess)) if iterations < max_iterations => Sample {
+                iterations,
+                fraction: -excess as f32,
+                root: root as u32 + 1,
+                ..sample
+            },
+            _ => Sample {
+                iterations: max_iterations,
+                interior: true,
+                ..sample
+            },
+        }
+    }
+
+    /// Continuous iteration count that interpolates between the iterations of neighbouring
+    /// escaped points
+    fn smooth_iterations(&self) -> f64 {
+        self.iterations as f64 + self.fraction as f64
+    }
+
+    /// Whether `self` and `other` get the same color from every coloring that only uses what
+    /// is calculated with `tracking`
+    ///
+    /// Points that did not escape only differ if their orbits are used at all.
+    fn looks_like(&self, other: &Sample, tracking: &TrackingOptions) -> bool {
+        (self.interior && other.interior && !tracking.interior) || self == other
+    }
+}
+
+impl Frame {
+    /// Full resolution tiles a frame of the given size is split into
+    fn tile_areas(width: usize, height: usize) -> Vec<TileArea> {
+        (0..height.div_ceil(TILE_SIZE))
+            .flat_map(|y| (0..width.div_ceil(TILE_SIZE)).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = (x * TILE_SIZE, y * TILE_SIZE);
+                TileArea {
+                    x,
+                    y,
+                    width: usize::min(TILE_SIZE, width - x),
+                    height: usize::min(TILE_SIZE, height - y),
+                    scale: 1,
+                }
+            })
+            .collect()
+    }
+
+    fn sample(&self, x: usize, y: usize) -> Sample {
+        let columns = self.width.div_ceil(TILE_SIZE);
+        let tile_x = x / TILE_SIZE * TILE_SIZE;
+        let tile_width = usize::min(TILE_SIZE, self.width - tile_x);
+
+        self.tiles[y / TILE_SIZE * columns + x / TILE_SIZE]
+            [y % TILE_SIZE * tile_width + x % TILE_SIZE]
+    }
+
+    /// Offset in whole pixels of `view` relative to this frame
+    ///
+    /// Returns `None` if the pixels of this frame can't be reused for rendering `view`
+    /// with the given size and iteration limit.
+    fn offset(
+        &self,
+        view: &View,
+        (width, height): (usize, usize),
+        max_iterations: u32,
+    ) -> Option<(isize, isize)> {
+        if (self.width, self.height, self.max_iterations) != (width, height, max_iterations)
+            || self.view.width() != view.width()
+            || self.view.height() != view.height()
+        {
+            return None;
+        }
+
+        let dx = (view.center_x() - self.view.center_x()).to_float_exp()
+            * FloatExp::from_f64(width as f64 - 1.0)
+            / view.width();
+        let dy = (view.center_y() - self.view.center_y()).to_float_exp()
+            * FloatExp::from_f64(height as f64 - 1.0)
+            / view.height();
+        let (dx, dy) = (dx.to_f64(), dy.to_f64());
+
+        // Only views that are moved by whole pixels and still overlap can be reused
+        if (dx - dx.round()).abs() > 1e-3
+            || (dy - dy.round()).abs() > 1e-3
+            || dx.abs() >= width as f64
+            || dy.abs() >= height as f64
+        {
+            return None;
+        }
+
+        Some((dx.round() as isize, dy.round() as isize))
+    }
+}
+
+impl View {
+    pub fn new(center_x: f64, center_y: f64, width: f64, height: f64) -> Self {
+        let (width, height) = (FloatExp::from_f64(width), FloatExp::from_f64(height));
+        let precision = View::precision_for(width, height);
+
+        View {
+            center_x: BigFixed::from_f64(center_x, precision),
+            center_y: BigFixed::from_f64(center_y, precision),
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> FloatExp {
+        self.width
+    }
+
+    pub fn height(&self) -> FloatExp {
+        self.height
+    }
+
+    /// Changes the size of the view while keeping its center
+    pub fn set_size(&mut self, width: f64, height: f64) {
+        self.resize(FloatExp::from_f64(width), FloatExp::from_f64(height));
+    }
+
+    /// Scales the size of the view by `x` and `y` while keeping its center
+    pub fn scale(&mut self, x: f64, y: f64) {
+        self.resize(
+            self.width * FloatExp::from_f64(x),
+            self.height * FloatExp::from_f64(y),
+        );
+    }
+
+    /// Zooms into the rectangle at `x`, `y` with size `width` and `height`
+    ///
+    /// All values are fractions of the current view size, with `0.0` being the top-left corner.
+    pub fn zoom(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.translate(x + width / 2.0 - 0.5, y + height / 2.0 - 0.5);
+        self.scale(width, height);
+    }
+
+    /// Point at `x`, `y` with the precision of `f64`
+    ///
+    /// Both values are fractions of the view size, with `0.0` being the top-left corner.
+    pub fn point(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.center_x.to_f64() + (self.width * FloatExp::from_f64(x - 0.5)).to_f64(),
+            self.center_y.to_f64() + (self.height * FloatExp::from_f64(y - 0.5)).to_f64(),
+        )
+    }
+
+    /// Moves the center of the view by fractions `x` and `y` of the view size
+    pub fn translate(&mut self, x: f64, y: f64) {
+        let precision = self.precision();
+        let dx = self.width * FloatExp::from_f64(x);
+        let dy = self.height * FloatExp::from_f64(y);
+
+        self.center_x = &self.center_x + &BigFixed::from_float_exp(dx, precision);
+        self.center_y = &self.center_y + &BigFixed::from_float_exp(dy, precision);
+    }
+
+    fn resize(&mut self, width: FloatExp, height: FloatExp) {
+        let precision = View::precision_for(width, height);
+
+        self.center_x = self.center_x.with_precision(precision);
+        self.center_y = self.center_y.with_precision(precision);
+        self.width = width;
+        self.height = height;
+    }
+
+    fn center_x(&self) -> &BigFixed {
+        &self.center_x
+    }
+
+    fn center_y(&self) -> &BigFixed {
+        &self.center_y
+    }
+
+    /// Number of fractional limbs of the center
+    fn precision(&self) -> usize {
+        self.center_x.precision()
+    }
+
+    /// Number of fractional limbs needed for the center of a view of the given size
+    ///
+    /// This keeps 64 bits more than needed for distinguishing points inside the view.
+    fn precision_for(width: FloatExp, height: FloatExp) -> usize {
+        let size = FloatExp::min(width, height);
+        let bits = if size > FloatExp::from_f64(0.0) {
+            f64::max(-size.log2(), 0.0) + 64.0
+        } else {
+            64.0
+        };
+
+        (bits / 32.0).ceil() as usize
+    }
+}
+
+pub fn render_thread(commands: &mpsc::Receiver<Command>, tiles: &async_channel::Sender<Tile>) {
+    let mut previous_frame: Option<Frame> = None;
+    // Command that interrupted rendering a density
+    let mut pending = None;
+
+    loop {
+        let mut command = match pending.take() {
+            Some(command) => command,
+            None => commands.recv().unwrap(),
+        };
+
+        // Get last command that was ever send, but always break on quit. Recoloring a frame
+        // that is not rendered yet only changes the coloring it is rendered with, densities
+        // don't use the coloring.
+        while let Ok(cmd) = commands.try_recv() {
+            if let (Command::RenderDensity { .. }, Command::Recolor { .. }) = (&command, &cmd) {
+                continue;
+            }
+
+            if let (
+                Command::Render {
+                    coloring, frame, ..
+                },
+                Command::Recolor {
+                    coloring: new_coloring,
+                    frame: new_frame,
+                },
+            ) = (&mut command, &cmd)
+            {
+                *coloring = new_coloring.clone();
+                *frame = *new_frame;
+                continue;
+            }
+
+            command = cmd;
+            if let Command::Quit = command {
+                break;
+            }
+        }
+
+        match command {
+            Command::Quit => break,
+            Command::Render {
+                view,
+                target_width,
+                target_height,
+                max_iterations,
+                iteration_mode,
+                subdivision,
+                fractal,
+                julia,
+                coloring,
+                frame,
+                cancel,
+            } => {
+                let max_iterations = iteration_limit(
+                    iteration_mode,
+                    max_iterations,
+                    &view,
+                    previous_frame.as_ref(),
+                );
+                // The distribution of the new frame is only known once it is complete, until
+                // then the one of the previous frame is the best guess
+                let histogram = match coloring.mapping {
+                    ColorMapping::Histogram => previous_frame.as_ref().map(Histogram::new),
+                    ColorMapping::Smooth => None,
+                };
+                let rendered = render_frame(
+                    view,
+                    (target_width, target_height),
+                    max_iterations,
+                    subdivision,
+                    fractal,
+                    julia,
+                    &coloring,
+                    previous_frame.as_ref(),
+                    &cancel,
+                    |area, samples| {
+                        if !cancel.is_cancelled() {
+                            let tile = Tile::new(
+                                area,
+                                samples,
+                                &coloring,
+                                histogram.as_ref(),
+                                frame,
+                                max_iterations,
+                            );
+                            tiles.send_blocking(tile).unwrap();
+                        }
+                    },
+                );
+                if let Some(rendered) = rendered {
+                    if coloring.mapping == ColorMapping::Histogram && !cancel.is_cancelled() {
+                        color_frame(&rendered, &coloring, frame, tiles);
+                    }
+                    previous_frame = Some(rendered);
+                }
+            }
+            Command::RenderDensity {
+                view,
+                target_width,
+                target_height,
+                max_iterations,
+                mode,
+                fractal,
+                julia,
+                frame,
+                cancel,
+            } => {
+                pending = density::render_density(
+                    &view,
+                    (target_width, target_height),
+                    max_iterations,
+                    mode,
+                    fractal,
+                    julia,
+                    frame,
+                    &cancel,
+                    commands,
+                    tiles,
+                );
+            }
+            Command::Recolor { coloring, frame } => {
+                if let Some(ref previous_frame) = previous_frame {
+                    color_frame(previous_frame, &coloring, frame, tiles);
+                }
+            }
+        }
+    }
+}
+
+/// Colors all tiles of a completely rendered frame and sends them as part of `frame_number`
+fn color_frame(
+    frame: &Frame,
+    coloring: &Coloring,
+    frame_number: u64,
+    tiles: &async_channel::Sender<Tile>,
+) {
+    let histogram = match coloring.mapping {
+        ColorMapping::Histogram => Some(Histogram::new(frame)),
+        ColorMapping::Smooth => None,
+    };
+
+    Frame::tile_areas(frame.width, frame.height)
+        .into_par_iter()
+        .zip(&frame.tiles)
+        .for_each(|(area, samples)| {
+            let tile = Tile::new(
+                area,
+                samples,
+                coloring,
+                histogram.as_ref(),
+                frame_number,
+                frame.max_iterations,
+            );
+            tiles.send_blocking(tile).unwrap();
+        });
+}
+
+/// Derive the iteration limit for rendering `view` from the user-selected `max_iterations`
+///
+/// `previous_frame` is the previously rendered frame, if any.
+fn iteration_limit(
+    mode: IterationMode,
+    max_iterations: u32,
+    view: &View,
+    previous_frame: Option<&Frame>,
+) -> u32 {
+    // Magnification relative to the initial view, grows roughly by one
+    // iteration budget per order of magnitude
+    let log2_zoom = f64::max(
+        MIN_WIDTH.log2() - view.width().log2(),
+        MIN_HEIGHT.log2() - view.height().log2(),
+    );
+    let log_scaled =
+        max_iterations as f64 * (1.0 + f64::max(log2_zoom * std::f64::consts::LOG10_2, 0.0));
+
+    let limit = match mode {
+        IterationMode::Fixed => return max_iterations,
+        IterationMode::LogScaled => log_scaled,
+        IterationMode::Adaptive => match previous_frame {
+            None => log_scaled,
+            Some(&Frame {
+                max_iterations: previous_limit,
+                ref stats,
+                ..
+            }) => {
+                let total = stats.escaped + stats.interior;
+                if stats.escaped_late * 1000 > total {
+                    // A noticeable number of points only escaped shortly before the
+                    // limit, so probably many interior points would still escape
+                    previous_limit as f64 * 2.0
+                } else if stats.max_escaped < previous_limit / 4 {
+                    // Large gap between the slowest escaping point and the limit,
+                    // so the interior points are most likely really part of the set
+                    f64::max(stats.max_escaped as f64 * 4.0, max_iterations as f64)
+                } else {
+                    previous_limit as f64
+                }
+            }
+        },
+    };
+
+    f64::clamp(limit, 1.0, MAX_MAX_ITERATIONS as f64) as u32
+}
+
+/// Iterates points with plain `f64` or relative to a high precision reference orbit
+enum EscapeTime {
+    Direct {
+        center: Complex64,
+        size: Complex64,
+        pixel_size: f64,
+        options: TrackingOptions,
+        fractal: Fractal,
+        /// Constant `c` of the Julia set whose points are iterated, if any
+        julia: Option<Complex64>,
+    },
+    Perturbed(Perturbation<f64>),
+    /// Like `Perturbed` but with an extended exponent range for views that are too small
+    /// for the differences between pixels to be represented by `f64`
+    PerturbedDeep(Perturbation<FloatExp>),
+}
+
+impl EscapeTime {
+    /// Returns `None` if rendering was cancelled while computing the reference orbit
+    ///
+    /// `options` determines what is tracked along the orbit of every point. With `julia`, the
+    /// points are the starting values of orbits of that constant instead, like they always
+    /// are for Newton's method. Perturbation only exists for the Mandelbrot set, so Julia sets
+    /// and other fractals are always iterated directly and can't be zoomed into beyond the
+    /// precision of `f64`.
+    fn new(
+        view: &View,
+        pixel_size: FloatExp,
+        max_iterations: u32,
+        options: TrackingOptions,
+        fractal: Fractal,
+        julia: Option<Complex64>,
+        cancel: &CancellationToken,
+    ) -> Option<Self> {
+        let escape_time = if julia.is_some() || fractal != Fractal::Mandelbrot {
+            EscapeTime::direct(view, pixel_size, options, fractal, julia)
+        } else if pixel_size < FloatExp::from_f64(DEEP_PERTURBATION_THRESHOLD) {
+            EscapeTime::PerturbedDeep(Perturbation::new(
+                view,
+                pixel_size,
+                max_iterations,
+                options,
+                cancel,
+            )?)
+        } else if pixel_size < FloatExp::from_f64(PERTURBATION_THRESHOLD) {
+            EscapeTime::Perturbed(Perturbation::new(
+                view,
+                pixel_size,
+                max_iterations,
+                options,
+                cancel,
+            )?)
+        } else {
+            EscapeTime::direct(view, pixel_size, options, fractal, None)
+        };
+
+        Some(escape_time)
+    }
+
+    fn direct(
+        view: &View,
+        pixel_size: FloatExp,
+        options: TrackingOptions,
+        fractal: Fractal,
+        julia: Option<Complex64>,
+    ) -> Self {
+        EscapeTime::Direct {
+            center: Complex64::new(view.center_x().to_f64(), view.center_y().to_f64()),
+            size: Complex64::new(view.width().to_f64(), view.height().to_f64()),
+            pixel_size: pixel_size.to_f64(),
+            options,
+            fractal,
+            julia,
+        }
+    }
+
+    /// Point at `position` relative to the view center, in units of the view size, with the
+    /// precision of `f64`
+    fn point(&self, position: Complex64) -> Complex64 {
+        match self {
+            EscapeTime::Direct { center, size, .. } => {
+                center + Complex64::new(position.re * size.re, position.im * size.im)
+            }
+            EscapeTime::Perturbed(perturbation) => perturbation.point(position),
+            EscapeTime::PerturbedDeep(perturbation) => perturbation.point(position),
+        }
+    }
+
+    /// Constant `c` of the orbit of the point at `position`
+    fn parameter(&self, position: Complex64) -> Complex64 {
+        match self {
+            EscapeTime::Direct {
+                julia: Some(julia), ..
+            } => *julia,
+            _ => self.point(position),
+        }
+    }
+
+    /// Iterates the point at `position` relative to the view center, in units of the
+    /// view size
+    ///
+    /// Returns the number of iterations and the last value of the orbit, and the quantities
+    /// that were tracked according to the options given on creation. The derivative of the
+    /// last value with respect to the point is in units of the pixel size.
+    fn iterate(&self, position: Complex64, max_iterations: u32) -> (u32, Complex64, Tracking<f64>) {
+        match self {
+            EscapeTime::Direct {
+                pixel_size,
+                options,
+                fractal,
+                julia,
+                ..
+            } => {
+                let point = self.point(position);
+                // The derivative with respect to `c` gains one every iteration, the one with
+                // respect to the starting value starts at one instead
+                let (mut z, c, dc) = match (julia, fractal) {
+                    (Some(julia), _) => (point, *julia, 0.0),
+                    (None, Fractal::Newton(_)) => (point, Complex64::new(0.0, 0.0), 0.0),
+                    (None, _) => (Complex64::new(0.0, 0.0), point, *pixel_size),
+                };
+                let mut tracking = Tracking::new(options, z, c);
+                if let (0.0, Some(dz)) = (dc, tracking.derivative.as_mut()) {
+                    *dz = Complex64::new(*pixel_size, 0.0);
+                }
+
+                // Points that are known to be inside don't have to be iterated up to the
+                // limit unless something about their orbit is used
+                let shortcut = !options.interior && fractal.is_memoryless();
+                if shortcut
+                    && julia.is_none()
+                    && *fractal == Fractal::Mandelbrot
+                    && in_main_components(c)
+                {
+                    return (max_iterations, z, tracking);
+                }
+
+                // Brent's cycle detection: the orbit is periodic if it returns to the value it
+                // had at the last power of two iterations
+                let mut check = z;
+                let mut next_check = 1;
+                let mut it = 0;
+                // Values before the current ones, which only some fractals depend on
+                let (mut previous_z, mut previous_dz) =
+                    (Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0));
+
+                while !fractal.escaped(z, c) && it < max_iterations {
+                    if let Some(ref mut dz) = tracking.derivative {
+                        *dz = fractal.step_derivative(z, *dz, c, dc, &mut previous_dz);
+                    }
+                    z = fractal.step(z, c, &mut previous_z);
+                    it += 1;
+                    if let Some(ref mut statistic) = tracking.statistic {
+                        statistic.add(z);
+                    }
+                    if let Some(ref mut atom_domain) = tracking.atom_domain {
+                        atom_domain.add(z, it);
+                    }
+
+                    if shortcut {
+                        if (z - check).norm_sqr() < PERIODICITY_TOLERANCE {
+                            return (max_iterations, z, tracking);
+                        }
+                        if it == next_check {
+                            check = z;
+                            next_check *= 2;
+                        }
+                    }
+                }
+
+                (it, z, tracking)
+            }
+            EscapeTime::Perturbed(perturbation) => perturbation.iterate(position, max_iterations),
+            EscapeTime::PerturbedDeep(perturbation) => {
+                perturbation.iterate(position, max_iterations)
+            }
+        }
+    }
+
+    /// Iterates the points at all `positions` like `iterate`
+    ///
+    /// If nothing has to be tracked while iterating, points are iterated directly several at
+    /// once with SIMD instructions if the CPU supports them.
+    fn iterate_all(
+        &self,
+        positions: &[Complex64],
+        max_iterations: u32,
+    ) -> Vec<(u32, Complex64, Tracking<f64>)> {
+        if let EscapeTime::Direct {
+            options,
+            fractal: Fractal::Mandelbrot,
+            julia,
+            ..
+        } = self
+        {
+            if !options.tracks_orbit() {
+                let points = positions
+                    .iter()
+                    .map(|&position| self.point(position))
+                    .collect::<Vec<_>>();
+                if let Some(results) =
+                    simd::iterate(&points, *julia, max_iterations, !options.interior)
+                {
+                    return results
+                        .into_iter()
+                        .map(|(it, z)| (it, z, Tracking::default()))
+                        .collect();
+                }
+            }
+        }
+
+        positions
+            .iter()
+            .map(|&position| self.iterate(position, max_iterations))
+            .collect()
+    }
+}
+
+/// Calculates the samples of a `width` × `height` area by Mariani–Silver subdivision
+///
+/// Rectangles whose border samples all look the same with what is calculated with `tracking`
+/// are filled with a sample of their border without calculating their inside, all others are
+/// split in half. In practice only rectangles inside the set are filled, as the smooth
+/// iteration counts of escaped points differ everywhere.
+/// This is exact for the interior of the set, which has no holes, but can miss details that
+/// don't reach the border of a rectangle for other fractals.
+///
+/// `reuse` returns the samples that are known already and `compute` calculates all others.
+/// Returns `None` if rendering was cancelled.
+fn subdivide(
+    width: usize,
+    height: usize,
+    tracking: &TrackingOptions,
+    reuse: impl Fn(usize, usize) -> Option<Sample>,
+    compute: impl Fn(usize, usize) -> Sample,
+    cancel: &CancellationToken,
+) -> Option<Vec<Sample>> {
+    let mut samples = vec![None; width * height];
+    let sample = |samples: &mut [Option<Sample>], x: usize, y: usize| -> Sample {
+        *samples[y * width + x].get_or_insert_with(|| reuse(x, y).unwrap_or_else(|| compute(x, y)))
+    };
+
+    // Rectangles with inclusive bounds, each sharing its border with its neighbours
+    let mut rectangles = vec![(0, 0, width - 1, height - 1)];
+    while let Some((x0, y0, x1, y1)) = rectangles.pop() {
+        if cancel.is_cancelled() {
+            return None;
+        }
+
+        let mut border = (x0..=x1)
+            .flat_map(|x| [(x, y0), (x, y1)])
+            .chain((y0..=y1).flat_map(|y| [(x0, y), (x1, y)]));
+        let (x, y) = border.next().expect("rectangles are never empty");
+        let first = sample(&mut samples, x, y);
+        let uniform = border.all(|(x, y)| sample(&mut samples, x, y).looks_like(&first, tracking));
+
+        let inside = (x0 + 1..x1).flat_map(|x| (y0 + 1..y1).map(move |y| (x, y)));
+        if uniform {
+            for (x, y) in inside {
+                samples[y * width + x] = Some(reuse(x, y).unwrap_or(first));
+            }
+        } else if x1 - x0 < MIN_SUBDIVISION_SIZE || y1 - y0 < MIN_SUBDIVISION_SIZE {
+            // Also calculates the rest of the border that was not checked anymore
+            for (x, y) in (x0..=x1).flat_map(|x| (y0..=y1).map(move |y| (x, y))) {
+                sample(&mut samples, x, y);
+            }
+        } else if x1 - x0 >= y1 - y0 {
+            let middle = (x0 + x1) / 2;
+            rectangles.push((x0, y0, middle, y1));
+            rectangles.push((middle, y0, x1, y1));
+        } else {
+            let middle = (y0 + y1) / 2;
+            rectangles.push((x0, y0, x1, middle));
+            rectangles.push((x0, middle, x1, y1));
+        }
+    }
+
+    Some(
+        samples
+            .into_iter()
+            .map(|sample| sample.expect("all samples are calculated or filled"))
+            .collect(),
+    )
+}
+
+/// Whether `c` is inside the main cardioid or the period-2 bulb, which are entirely part of
+/// the set
+fn in_main_components(c: Complex64) -> bool {
+    let y2 = c.im * c.im;
+    let q = (c.re - 0.25) * (c.re - 0.25) + y2;
+
+    q * (q + (c.re - 0.25)) <= 0.25 * y2 || (c.re + 1.0) * (c.re + 1.0) + y2 <= 1.0 / 16.0
+}
+
+/// Renders `view` tile by tile in progressively finer passes
+///
+/// The samples of every tile are passed to `tile_done` as soon as it is finished. If `view` is
+/// only moved by whole pixels relative to `previous_frame`, its samples are reused and only
+/// the newly exposed pixels are calculated. Returns the full resolution frame, or `None` if
+/// rendering was cancelled.
+///
+/// The distance of every escaped point to the boundary, its surface normal, the statistic and
+/// atom domain of its orbit, and the attracting cycle of points that did not escape are
+/// calculated if `coloring` uses them.
+///
+/// With `subdivision`, uniform rectangles of every tile are filled without calculating their
+/// inside, see `subdivide`. With `julia`, the Julia set of that constant is rendered instead of
+/// the set of all `c` of `fractal`.
+#[allow(clippy::too_many_arguments)]
+fn render_frame(
+    view: View,
+    (target_width, target_height): (usize, usize),
+    max_iterations: u32,
+    subdivision: bool,
+    fractal: Fractal,
+    julia: Option<Complex64>,
+    coloring: &Coloring,
+    previous_frame: Option<&Frame>,
+    cancel: &CancellationToken,
+    tile_done: impl Fn(TileArea, &[Sample]) + Sync,
+) -> Option<Frame> {
+    let (xscale, yscale) = (
+        1.0 / (target_width as f64 - 1.0),
+        1.0 / (target_height as f64 - 1.0),
+    );
+
+    let tracking = coloring.tracking_options();
+    let pixel_size = FloatExp::min(
+        view.width() * FloatExp::from_f64(xscale),
+        view.height() * FloatExp::from_f64(yscale),
+    );
+
+    let offset = previous_frame
+        .filter(|previous_frame| {
+            previous_frame.fractal == fractal
+                && previous_frame.julia == julia
+                && previous_frame.tracking.covers(&tracking)
+                && (subdivision || !previous_frame.subdivided)
+        })
+        .and_then(|previous_frame| {
+            previous_frame
+                .offset(&view, (target_width, target_height), max_iterations)
+                .map(|offset| (previous_frame, offset))
+        });
+
+    // Nothing has to be calculated if the view did not move at all
+    let escape_time = match offset {
+        Some((_, (0, 0))) => None,
+        _ => Some(EscapeTime::new(
+            &view,
+            pixel_size,
+            max_iterations,
+            tracking,
+            fractal.clone(),
+            julia,
+            cancel,
+        )?),
+    };
+
+    let tiles = Frame::tile_areas(target_width, target_height);
+
+    // Newly exposed pixels are rendered at full resolution right away
+    let passes: &[usize] = if offset.is_some() { &[1] } else { &PASSES };
+
+    let mut previous_pass: Option<Vec<Vec<_>>> = None;
+    for &scale in passes {
+        let pass = tiles
+            .par_iter()
+            .enumerate()
+            .map(|(i, tile)| {
+                let area = TileArea {
+                    width: tile.width.div_ceil(scale),
+                    height: tile.height.div_ceil(scale),
+                    scale,
+                    ..*tile
+                };
+                let previous_width = tile.width.div_ceil(2 * scale);
+
+                // Samples that are known already from the previous pass or frame
+                let reuse = |x: usize, y: usize| -> Option<Sample> {
+                    // Every other pixel of every other row was calculated by the previous pass
+                    if let Some(ref previous) = previous_pass {
+                        if x.is_multiple_of(2) && y.is_multiple_of(2) {
+                            return Some(previous[i][y / 2 * previous_width + x / 2]);
+                        }
+                    }
+
+                    let (previous_frame, (dx, dy)) = offset?;
+                    let source_x = (tile.x + x * scale).checked_add_signed(dx)?;
+                    let source_y = (tile.y + y * scale).checked_add_signed(dy)?;
+                    (source_x < target_width && source_y < target_height)
+                        .then(|| previous_frame.sample(source_x, source_y))
+                };
+                let position = |x: usize, y: usize| {
+                    Complex64::new(
+                        (tile.x + x * scale) as f64 * xscale - 0.5,
+                        (tile.y + y * scale) as f64 * yscale - 0.5,
+                    )
+                };
+                let escape_time = || {
+                    escape_time
+                        .as_ref()
+                        .expect("escape time only skipped if all pixels are reused")
+                };
+                let sample = |position: Complex64, (it, z, tracked): (u32, Complex64, _)| {
+                    if let Fractal::Newton(ref polynomial) = fractal {
+                        return Sample::newton(it, z, polynomial, max_iterations);
+                    }
+
+                    // Cycles are only searched for the Mandelbrot iteration
+                    let cycle =
+                        if tracking.cycle && fractal == Fractal::Mandelbrot && it >= max_iterations
+                        {
+                            Cycle::find(
+                                z,
+                                escape_time().parameter(position),
+                                pixel_size.to_f64(),
+                                max_iterations,
+                            )
+                            // The interior distance is only known in the parameter plane
+                            .map(|cycle| match julia {
+                                Some(_) => Cycle {
+                                    distance: f64::INFINITY,
+                                    ..cycle
+                                },
+                                None => cycle,
+                            })
+                        } else {
+                            None
+                        };
+                    Sample::new(it, z, tracked, cycle, max_iterations, fractal.degree())
+                };
+
+                let samples = if subdivision {
+                    let compute = |x: usize, y: usize| {
+                        let position = position(x, y);
+                        sample(position, escape_time().iterate(position, max_iterations))
+                    };
+                    subdivide(area.width, area.height, &tracking, reuse, compute, cancel)?
+                } else {
+                    let mut samples = Vec::with_capacity(area.width * area.height);
+                    for y in 0..area.height {
+                        if cancel.is_cancelled() {
+                            return None;
+                        }
+
+                        // All missing samples of a row are calculated together
+                        let known = (0..area.width).map(|x| reuse(x, y)).collect::<Vec<_>>();
+                        let missing = (0..area.width)
+                            .filter(|&x| known[x].is_none())
+                            .map(|x| position(x, y))
+                            .collect::<Vec<_>>();
+                        let mut calculated = if missing.is_empty() {
+                            Vec::new()
+                        } else {
+                            escape_time().iterate_all(&missing, max_iterations)
+                        }
+                        .into_iter()
+                        .zip(&missing)
+                        .map(|(result, &position)| sample(position, result));
+
+                        samples.extend(known.into_iter().map(|known| {
+                            known.unwrap_or_else(|| {
+                                calculated.next().expect("one result per missing sample")
+                            })
+                        }));
+                    }
+                    samples
+                };
+
+                tile_done(area, &samples);
+
+                Some(samples)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        previous_pass = Some(pass);
+    }
+
+    let tiles = previous_pass?;
+    let stats = iteration_stats(tiles.par_iter().flatten(), max_iterations);
+
+    Some(Frame {
+        view,
+        width: target_width,
+        height: target_height,
+        max_iterations,
+        tiles,
+        stats,
+        tracking,
+        subdivided: subdivision,
+        fractal,
+        julia,
+    })
+}
+
+/// Escape statistics of the samples of a frame
+fn iteration_stats<'a>(
+    samples: impl ParallelIterator<Item = &'a Sample>,
+    max_iterations: u32,
+) -> IterationStats {
+    samples
+        .fold(IterationStats::default, |mut stats, sample| {
+            if sample.interior {
+                stats.interior += 1;
+            } else {
+                stats.escaped += 1;
+                if sample.iterations > max_iterations / 2 {
+                    stats.escaped_late += 1;
+                }
+                stats.max_escaped = u32::max(stats.max_escaped, sample.iterations);
+
+                let iterations = sample.iterations as usize;
+                if stats.counts.len() <= iterations {
+                    stats.counts.resize(iterations + 1, 0);
+                }
+                stats.counts[iterations] += 1;
+            }
+            stats
+        })
+        .reduce(IterationStats::default, |a, b| {
+            let (mut counts, other) = if a.counts.len() >= b.counts.len() {
+                (a.counts, b.counts)
+            } else {
+                (b.counts, a.counts)
+            };
+            for (count, other) in counts.iter_mut().zip(other) {
+                *count += other;
+            }
+
+            IterationStats {
+                escaped: a.escaped + b.escaped,
+                escaped_late: a.escaped_late + b.escaped_late,
+                max_escaped: u32::max(a.max_escaped, b.max_escaped),
+                interior: a.interior + b.interior,
+                counts,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodicity_check() {
+        const MAX_ITERATIONS: u32 = 10_000;
+
+        const SIZE: usize = 48;
+
+        let (mut inside, mut slow) = (0, 0);
+        for (_, view, julia) in support::boundary_views() {
+            let skipped = support::Grid::new(&view, julia, SIZE, true).iterate(MAX_ITERATIONS);
+            let iterated = support::Grid::new(&view, julia, SIZE, false).iterate(MAX_ITERATIONS);
+
+            // Points that escape do so after exactly the same iterations with the check
+            for (skipped, iterated) in skipped.iter().zip(&iterated) {
+                assert_eq!(skipped.0, iterated.0, "{:?} {:?}", view, julia);
+                if iterated.0 < MAX_ITERATIONS {
+                    assert_eq!(skipped.1, iterated.1);
+                }
+            }
+
+            inside += iterated
+                .iter()
+                .filter(|&&(it, _)| it == MAX_ITERATIONS)
+                .count();
+            slow += iterated
+                .iter()
+                .filter(|&&(it, _)| (1000..MAX_ITERATIONS).contains(&it))
+                .count();
+        }
+
+        // Both points inside and slowly escaping ones are part of the views
+        assert!(inside > 0 && slow > 0);
+    }
+
+    #[test]
+    fn fraction() {
+        let fraction = |magnitude: f64, degree: f64| {
+            let tracking = Tracking {
+                derivative: None,
+                statistic: None,
+                atom_domain: None,
+            };
+            let z = Complex64::new(magnitude, 0.0);
+            Sample::new(10, z, tracking, None, 100, degree).fraction
+        };
+
+        // Escaping from the default bailout
+        let bailout = BAILOUT.sqrt();
+        assert!((fraction(bailout, 2.0) - fraction(bailout * bailout, 2.0) - 1.0).abs() < 1e-6);
+        // Escaping from bailouts of formulas close to or inside the unit circle
+        for magnitude in [0.0, 0.5, 1.0, 1.5, 2.0, f64::INFINITY, f64::NAN] {
+            for degree in [1.5, 2.0, 3.0] {
+                assert_eq!(fraction(magnitude, degree), 1.0);
+            }
+        }
+        assert!(fraction(2.5, 2.0) < 1.0);
+    }
+
+    #[test]
+    fn subdivision() {
+        use std::sync::atomic::AtomicUsize;
+
+        const WIDTH: usize = 80;
+        const HEIGHT: usize = 60;
+
+        // Disk of points inside the set surrounded by a smooth gradient within a single
+        // iteration band
+        let sample = |x: usize, y: usize| {
+            let distance = f32::hypot(x as f32 - 40.0, y as f32 - 30.0);
+            if distance < 20.0 {
+                Sample {
+                    iterations: 100,
+                    interior: true,
+                    magnitude: distance,
+                    ..Sample::default()
+                }
+            } else {
+                Sample {
+                    iterations: 5,
+                    fraction: distance / 100.0,
+                    ..Sample::default()
+                }
+            }
+        };
+        let subdivide = |tracking: TrackingOptions| {
+            let computed = AtomicUsize::new(0);
+            let compute = |x, y| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                sample(x, y)
+            };
+            let samples = subdivide(
+                WIDTH,
+                HEIGHT,
+                &tracking,
+                |_, _| None,
+                compute,
+                &CancellationToken::default(),
+            )
+            .unwrap();
+            (samples, computed.into_inner())
+        };
+
+        // Only the inside is filled, about half of the disk's area, and the gradient outside
+        // is kept
+        let (samples, computed) = subdivide(TrackingOptions::default());
+        assert!(WIDTH * HEIGHT - computed > 500);
+        for (i, subdivided) in samples.iter().enumerate() {
+            let expected = sample(i % WIDTH, i / WIDTH);
+            if expected.interior {
+                assert!(subdivided.interior);
+            } else {
+                assert_eq!(*subdivided, expected);
+            }
+        }
+
+        // Nothing is filled if the orbits inside are used
+        let (samples, computed) = subdivide(TrackingOptions {
+            interior: true,
+            ..TrackingOptions::default()
+        });
+        assert_eq!(computed, WIDTH * HEIGHT);
+        for (i, subdivided) in samples.iter().enumerate() {
+            assert_eq!(*subdivided, sample(i % WIDTH, i / WIDTH));
+        }
+    }
+}
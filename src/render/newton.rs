@@ -0,0 +1,363 @@
+use gtk::glib;
+
+use num_complex::Complex64;
+
+use std::sync::Arc;
+
+use super::formula::{self, ParseError};
+
+/// Distance to a simple root below which Newton's method counts as converged
+///
+/// Roots of multiplicity `m` can only be approached to about the `m`th root of the precision,
+/// so the `m`th root of this is used for them.
+const CONVERGENCE_TOLERANCE: f64 = 1e-6;
+
+/// Roots of a polynomial with a multiplicity above one are only found up to about this
+/// precision, so closer ones are merged
+const ROOT_MERGE_DISTANCE: f64 = 1e-4;
+
+/// Maximum number of iterations for finding the roots of a polynomial given by coefficients
+const MAX_ROOT_ITERATIONS: usize = 1000;
+
+/// Maximum distance of the roots from the origin
+///
+/// The initial view is centered on the roots, and big enough to show all of them. This keeps
+/// it far inside the range of `BigFixed`.
+const MAX_ROOT_MAGNITUDE: f64 = 1e6;
+
+/// How the polynomial of the Newton fractal is typed in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PolynomialInput {
+    /// List of its roots, each repeated according to its multiplicity
+    Roots,
+    /// List of its coefficients, starting with the one of the highest power
+    Coefficients,
+}
+
+/// Polynomial whose roots Newton's method converges to
+///
+/// It is typed in as a comma-separated list of constant expressions like `1, -0.5 + 0.866i`,
+/// which are either its roots or its coefficients. The roots of polynomials given by their
+/// coefficients are found numerically.
+#[derive(Clone, Debug, glib::Boxed)]
+#[boxed_type(name = "MandelbrotPolynomial")]
+pub struct Polynomial(Arc<Parsed>);
+
+#[derive(Debug)]
+struct Parsed {
+    input: PolynomialInput,
+    source: String,
+    /// Coefficients starting with the one of the highest power, which is never zero
+    coefficients: Vec<Complex64>,
+    /// Distinct roots in the order they were typed in or found, with the distance below which
+    /// Newton's method converged to them
+    roots: Vec<(Complex64, f64)>,
+}
+
+impl Polynomial {
+    /// Parses the roots or coefficients in `source`
+    ///
+    /// All of them have to be finite, and the roots at most `MAX_ROOT_MAGNITUDE` away from
+    /// the origin.
+    pub fn new(input: PolynomialInput, source: &str) -> Result<Self, ParseError> {
+        let values = formula::parse_constants(source)?;
+        if let Some((_, span)) = values.iter().find(|(value, _)| !value.is_finite()) {
+            return Err(ParseError::new(
+                source,
+                span.clone(),
+                "Expected a finite number",
+            ));
+        }
+        let out_of_range = format!("Expected roots closer than {MAX_ROOT_MAGNITUDE:e} to zero");
+
+        let (coefficients, roots) = match input {
+            PolynomialInput::Roots => {
+                if let Some((_, span)) = values
+                    .iter()
+                    .find(|(root, _)| root.norm() > MAX_ROOT_MAGNITUDE)
+                {
+                    return Err(ParseError::new(source, span.clone(), out_of_range));
+                }
+
+                let roots = values.into_iter().map(|(root, _)| root).collect::<Vec<_>>();
+                (expand(&roots), merge_roots(&roots))
+            }
+            PolynomialInput::Coefficients => {
+                let coefficients = values
+                    .into_iter()
+                    .map(|(coefficient, _)| coefficient)
+                    .skip_while(|coefficient| *coefficient == Complex64::new(0.0, 0.0))
+                    .collect::<Vec<_>>();
+                if coefficients.len() < 2 {
+                    return Err(ParseError::new(
+                        source,
+                        0..source.len(),
+                        "Expected a polynomial of degree one or higher, which has roots",
+                    ));
+                }
+
+                let roots = find_roots(&coefficients);
+                if !roots
+                    .iter()
+                    .all(|root| root.is_finite() && root.norm() <= MAX_ROOT_MAGNITUDE)
+                {
+                    return Err(ParseError::new(source, 0..source.len(), out_of_range));
+                }
+
+                (coefficients, merge_roots(&roots))
+            }
+        };
+
+        // Many roots far from zero give coefficients that are too large for `f64`
+        if !coefficients
+            .iter()
+            .all(|coefficient| coefficient.is_finite())
+        {
+            return Err(ParseError::new(
+                source,
+                0..source.len(),
+                "Expected fewer roots or roots closer to zero",
+            ));
+        }
+
+        Ok(Polynomial(Arc::new(Parsed {
+            input,
+            source: source.to_string(),
+            coefficients,
+            roots,
+        })))
+    }
+
+    pub fn input(&self) -> PolynomialInput {
+        self.0.input
+    }
+
+    pub fn source(&self) -> &str {
+        &self.0.source
+    }
+
+    /// Center of the roots and the largest distance of a root from it
+    pub fn bounds(&self) -> (Complex64, f64) {
+        let roots = &self.0.roots;
+        let center = roots.iter().map(|(root, _)| root).sum::<Complex64>() / roots.len() as f64;
+        let radius = roots
+            .iter()
+            .map(|(root, _)| (root - center).norm())
+            .fold(0.0, f64::max);
+
+        (center, radius)
+    }
+
+    /// Index of the root that `z` is close enough to for Newton's method to have converged
+    ///
+    /// Also returns the part of the last iteration that was not needed to get close enough,
+    /// between zero and one, assuming that the number of correct digits doubles every
+    /// iteration.
+    #[inline]
+    pub fn root(&self, z: Complex64) -> Option<(usize, f64)> {
+        self.0
+            .roots
+            .iter()
+            .enumerate()
+            .find(|(_, (root, tolerance))| (root - z).norm_sqr() < tolerance * tolerance)
+            .map(|(index, (root, tolerance))| {
+                let excess = f64::log2((root - z).norm().ln() / tolerance.ln());
+                (index, excess.clamp(0.0, 1.0))
+            })
+    }
+
+    /// Next value of Newton's method after `z`, `z - p(z) / p'(z)`
+    ///
+    /// This is not finite at the critical points of the polynomial.
+    #[inline]
+    pub fn step(&self, z: Complex64) -> Complex64 {
+        let (p, dp, _) = self.evaluate(z);
+        z - p / dp
+    }
+
+    /// Derivative of the next value of Newton's method from the derivative `dz` of `z`
+    #[inline]
+    pub fn step_derivative(&self, z: Complex64, dz: Complex64) -> Complex64 {
+        let (p, dp, ddp) = self.evaluate(z);
+        p * ddp / (dp * dp) * dz
+    }
+
+    /// Value of the polynomial and its first two derivatives at `z` by Horner's method
+    #[inline]
+    fn evaluate(&self, z: Complex64) -> (Complex64, Complex64, Complex64) {
+        let zero = Complex64::new(0.0, 0.0);
+        let (mut p, mut dp, mut ddp) = (zero, zero, zero);
+        for &coefficient in &self.0.coefficients {
+            ddp = ddp * z + 2.0 * dp;
+            dp = dp * z + p;
+            p = p * z + coefficient;
+        }
+
+        (p, dp, ddp)
+    }
+}
+
+impl Default for Polynomial {
+    fn default() -> Self {
+        Polynomial::new(PolynomialInput::Coefficients, "1, 0, 0, -1")
+            .expect("default polynomial is valid")
+    }
+}
+
+impl PartialEq for Polynomial {
+    fn eq(&self, other: &Self) -> bool {
+        self.input() == other.input() && self.source() == other.source()
+    }
+}
+
+/// Coefficients of the monic polynomial with the given `roots`
+fn expand(roots: &[Complex64]) -> Vec<Complex64> {
+    let mut coefficients = vec![Complex64::new(1.0, 0.0)];
+    for &root in roots {
+        // Multiply by `z - root`
+        coefficients.push(Complex64::new(0.0, 0.0));
+        for i in (1..coefficients.len()).rev() {
+            let previous = coefficients[i - 1];
+            coefficients[i] -= root * previous;
+        }
+    }
+
+    coefficients
+}
+
+/// All roots of the polynomial with the given `coefficients` by the Durand–Kerner method
+///
+/// The roots are refined simultaneously, each one by the value of the polynomial divided by
+/// the product of its distances to all other roots.
+fn find_roots(coefficients: &[Complex64]) -> Vec<Complex64> {
+    let degree = coefficients.len() - 1;
+    let leading = coefficients[0];
+    let monic = |z: Complex64| {
+        coefficients
+            .iter()
+            .fold(Complex64::new(0.0, 0.0), |p, &coefficient| {
+                p * z + coefficient
+            })
+            / leading
+    };
+
+    // Powers of a number that is neither real nor a root of unity are distinct starting
+    // values
+    let mut roots = (0..degree)
+        .map(|i| Complex64::new(0.4, 0.9).powu(i as u32))
+        .collect::<Vec<_>>();
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        let mut change = 0.0f64;
+        for i in 0..degree {
+            let denominator = (0..degree)
+                .filter(|&j| j != i)
+                .map(|j| roots[i] - roots[j])
+                .product::<Complex64>();
+            let step = monic(roots[i]) / denominator;
+            if step.is_finite() {
+                roots[i] -= step;
+                change = change.max(step.norm());
+            }
+        }
+
+        if change < f64::EPSILON {
+            break;
+        }
+    }
+
+    roots
+}
+
+/// `roots` with the ones that are too close to be told apart merged into their average, with
+/// their convergence tolerance
+///
+/// The roots found for a root with a multiplicity above one surround it, so their average is
+/// much closer to it than any of them.
+fn merge_roots(roots: &[Complex64]) -> Vec<(Complex64, f64)> {
+    // Sum and number of the roots of every group
+    let mut groups: Vec<(Complex64, f64)> = Vec::with_capacity(roots.len());
+    for &root in roots {
+        match groups
+            .iter_mut()
+            .find(|(sum, count)| (root - *sum / *count).norm() < ROOT_MERGE_DISTANCE)
+        {
+            Some((sum, count)) => {
+                *sum += root;
+                *count += 1.0;
+            }
+            None => groups.push((root, 1.0)),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(sum, count)| (sum / count, CONVERGENCE_TOLERANCE.powf(1.0 / count)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Column and message of the error in the polynomial given by `source`
+    fn error(input: PolynomialInput, source: &str) -> (usize, String) {
+        match Polynomial::new(input, source) {
+            Err(error) => (error.column, error.message),
+            Ok(polynomial) => panic!("Expected error, got {:?}", polynomial),
+        }
+    }
+
+    #[test]
+    fn roots() {
+        let polynomial = Polynomial::default();
+        assert_eq!(polynomial.0.roots.len(), 3);
+        for (root, _) in &polynomial.0.roots {
+            assert!((root.powu(3) - 1.0).norm() < 1e-12);
+            assert_eq!(polynomial.root(*root).map(|(_, excess)| excess), Some(1.0));
+        }
+
+        // Double root
+        let polynomial = Polynomial::new(PolynomialInput::Roots, "1, 1, -1").unwrap();
+        assert_eq!(
+            polynomial.0.coefficients,
+            expand(&[1.0.into(), 1.0.into(), (-1.0).into()])
+        );
+        assert_eq!(polynomial.0.roots.len(), 2);
+        let (center, radius) = polynomial.bounds();
+        assert_eq!((center, radius), (Complex64::new(0.0, 0.0), 1.0));
+    }
+
+    #[test]
+    fn range() {
+        for input in [PolynomialInput::Roots, PolynomialInput::Coefficients] {
+            assert_eq!(
+                error(input, "1, 1/0"),
+                (4, "Expected a finite number".to_string())
+            );
+            assert_eq!(
+                error(input, "1, 0/0"),
+                (4, "Expected a finite number".to_string())
+            );
+        }
+
+        let out_of_range = "Expected roots closer than 1e6 to zero".to_string();
+        assert_eq!(
+            error(PolynomialInput::Roots, "1, 2e6i"),
+            (4, out_of_range.clone())
+        );
+        assert_eq!(
+            error(PolynomialInput::Coefficients, "1, 1e300"),
+            (1, out_of_range)
+        );
+        assert_eq!(
+            error(PolynomialInput::Roots, &["9e5"; 60].join(", ")).1,
+            "Expected fewer roots or roots closer to zero"
+        );
+
+        // The initial view of the largest polynomials can still be created
+        for source in ["1e6, -1e6", "1e6i, 1e6i"] {
+            let polynomial = Polynomial::new(PolynomialInput::Roots, source).unwrap();
+            super::super::Fractal::Newton(polynomial).initial_view(false);
+        }
+    }
+}
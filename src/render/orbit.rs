@@ -0,0 +1,437 @@
+use gtk::glib;
+
+use num_complex::{Complex, Complex64};
+use num_traits::Zero;
+
+use std::f64::consts::PI;
+
+use super::BAILOUT;
+
+/// Algorithm that determines the palette position of escaped points
+#[derive(Debug, Copy, Clone, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MandelbrotColoringAlgorithm")]
+pub enum ColoringAlgorithm {
+    /// Smooth number of iterations until the point escaped
+    #[enum_value(name = "Escape time")]
+    EscapeTime,
+    /// Average of where every value of the orbit lies between the bounds of the triangle
+    /// inequality
+    #[enum_value(name = "Triangle inequality")]
+    TriangleInequality,
+    /// Average of stripes along the angle of every value of the orbit
+    #[enum_value(name = "Stripes")]
+    Stripe,
+    /// Average angle between consecutive steps of the orbit
+    #[enum_value(name = "Curvature")]
+    Curvature,
+    /// Closest distance of the orbit to a point
+    #[enum_value(name = "Point trap")]
+    PointTrap,
+    /// Closest distance of the orbit to a line
+    #[enum_value(name = "Line trap")]
+    LineTrap,
+    /// Closest distance of the orbit to two perpendicular lines
+    #[enum_value(name = "Cross trap")]
+    CrossTrap,
+}
+
+/// Value that is accumulated over the orbit of every point while iterating
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OrbitStatistic {
+    TriangleInequality,
+    Stripe {
+        /// Number of stripes per full turn
+        density: f64,
+    },
+    Curvature,
+    PointTrap {
+        center: Complex64,
+    },
+    LineTrap {
+        center: Complex64,
+        /// Angle of the line in radians
+        angle: f64,
+    },
+    CrossTrap {
+        center: Complex64,
+        /// Angle of one of the lines in radians
+        angle: f64,
+    },
+}
+
+/// What is calculated for every point in addition to its escape time, depending on what the
+/// coloring uses
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TrackingOptions {
+    /// Whether the derivative of the orbit is tracked
+    pub derivative: bool,
+    /// Statistic that is accumulated over the orbit, if any
+    pub statistic: Option<OrbitStatistic>,
+    /// Whether the iteration at which the orbit comes closest to zero is tracked
+    pub atom_domain: bool,
+    /// Whether the attracting cycle of points that did not escape is searched for
+    pub cycle: bool,
+    /// Whether the orbits of points that did not escape are used at all
+    ///
+    /// Otherwise they are not iterated up to the limit once they are known to be inside.
+    pub interior: bool,
+}
+
+/// Quantities that are tracked along the orbit of a point in addition to its values
+#[derive(Copy, Clone, Debug)]
+pub struct Tracking<T> {
+    /// Derivative with respect to `c` in units of the pixel size
+    pub derivative: Option<Complex<T>>,
+    pub statistic: Option<OrbitAccumulator>,
+    pub atom_domain: Option<AtomDomain>,
+}
+
+/// Iteration at which an orbit came closest to zero so far
+///
+/// Inside the set this is usually the period of the hyperbolic component the point belongs
+/// to, or of one close to it.
+#[derive(Copy, Clone, Debug)]
+pub struct AtomDomain {
+    pub iteration: u32,
+    norm_sqr: f64,
+}
+
+/// Attracting cycle of a point that did not escape
+#[derive(Copy, Clone, Debug)]
+pub struct Cycle {
+    pub period: u32,
+    /// Estimated distance to the boundary of the set from the inside
+    pub distance: f64,
+}
+
+/// Number of Newton steps for finding a point of the cycle exactly
+const CYCLE_NEWTON_STEPS: usize = 16;
+
+/// Distance between values of the orbit below which they are considered to be the same point
+/// of a cycle
+const CYCLE_TOLERANCE: f64 = 1e-10;
+
+/// Longest period of the cycles that are searched
+///
+/// Searching a cycle takes about as many iterations as its period for every Newton step, so
+/// this bounds the time spent on every point that did not escape independent of the iteration
+/// limit. Components with longer periods only become visible deep inside minibrots.
+const MAX_CYCLE_PERIOD: u32 = 4096;
+
+impl TrackingOptions {
+    /// Whether samples that were calculated with these options contain everything that
+    /// `other` calculates
+    pub fn covers(&self, other: &TrackingOptions) -> bool {
+        (self.derivative || !other.derivative)
+            && (self.statistic == other.statistic || other.statistic.is_none())
+            && (self.atom_domain || !other.atom_domain)
+            && (self.cycle || !other.cycle)
+            && (self.interior || !other.interior)
+    }
+
+    /// Whether anything is tracked while iterating, as opposed to only afterwards
+    pub fn tracks_orbit(&self) -> bool {
+        self.derivative || self.statistic.is_some() || self.atom_domain
+    }
+
+    /// Whether the first iterations of all points can be approximated together
+    ///
+    /// This is not possible if their values in these iterations matter individually, like
+    /// for the closest distance to a trap or to zero, which is often reached early on.
+    pub fn allows_skipping(&self) -> bool {
+        !self.atom_domain && !self.statistic.is_some_and(|statistic| statistic.is_trap())
+    }
+}
+
+impl<T: Clone + num_traits::Num> Tracking<T> {
+    /// Starts tracking the orbit of `c` from `Z(0) = z`
+    pub fn new(options: &TrackingOptions, z: Complex64, c: Complex64) -> Self {
+        Tracking {
+            derivative: options.derivative.then(Complex::zero),
+            statistic: options
+                .statistic
+                .map(|statistic| OrbitAccumulator::new(statistic, z, c)),
+            atom_domain: options.atom_domain.then(AtomDomain::default),
+        }
+    }
+}
+
+// Not derived as that would require `T: Default`
+impl<T> Default for Tracking<T> {
+    fn default() -> Self {
+        Tracking {
+            derivative: None,
+            statistic: None,
+            atom_domain: None,
+        }
+    }
+}
+
+impl Default for AtomDomain {
+    fn default() -> Self {
+        AtomDomain {
+            iteration: 0,
+            norm_sqr: f64::INFINITY,
+        }
+    }
+}
+
+impl AtomDomain {
+    /// Adds the value of the orbit after `iteration` iterations
+    #[inline]
+    pub fn add(&mut self, z: Complex64, iteration: u32) {
+        let norm_sqr = z.norm_sqr();
+        if norm_sqr < self.norm_sqr {
+            self.norm_sqr = norm_sqr;
+            self.iteration = iteration;
+        }
+    }
+}
+
+impl Cycle {
+    /// Searches the attracting cycle of `c` starting from `z`, the last value of its orbit
+    ///
+    /// `z` has to be close to the cycle already, which is the case after enough iterations
+    /// unless `c` is very close to the boundary. Cycles up to `max_period`, but not longer
+    /// than `MAX_CYCLE_PERIOD`, are searched and the distance is in units of `pixel_size`.
+    ///
+    /// This is calculated with `f64` only, which is not precise enough for the distance
+    /// once the pixel size gets close to the precision of `c`.
+    pub fn find(z: Complex64, c: Complex64, pixel_size: f64, max_period: u32) -> Option<Self> {
+        // The smallest period after which the orbit returns close to where it started, or
+        // the one after which it came closest if it didn't converge far enough yet
+        let mut w = z;
+        let mut closest = (f64::INFINITY, 0);
+        for period in 1..=u32::min(max_period, MAX_CYCLE_PERIOD) {
+            w = w * w + c;
+            let distance = (w - z).norm();
+            if distance < CYCLE_TOLERANCE {
+                closest = (distance, period);
+                break;
+            }
+            if distance < closest.0 {
+                closest = (distance, period);
+            }
+        }
+        let mut period = closest.1;
+        if period == 0 {
+            return None;
+        }
+
+        // Find a point of the cycle with Newton's method for `f^p(z) - z = 0`
+        let mut z0 = z;
+        for _ in 0..CYCLE_NEWTON_STEPS {
+            let (mut w, mut dw) = (z0, Complex64::new(1.0, 0.0));
+            for _ in 0..period {
+                dw = 2.0 * w * dw;
+                w = w * w + c;
+            }
+
+            let step = (w - z0) / (dw - 1.0);
+            if !step.is_finite() {
+                return None;
+            }
+            z0 -= step;
+            if step.norm() < f64::EPSILON * z0.norm() {
+                break;
+            }
+        }
+
+        // An orbit that did not converge far enough might have come closest after a multiple
+        // of the actual period
+        let mut w = z0;
+        for divisor in 1..period {
+            w = w * w + c;
+            if period % divisor == 0 && (w - z0).norm() < CYCLE_TOLERANCE {
+                period = divisor;
+                break;
+            }
+        }
+
+        // Derivatives of `f^p` at the cycle with respect to `z` and `c`
+        let (mut w, mut dz, mut dc) = (z0, Complex64::new(1.0, 0.0), Complex64::zero());
+        let (mut dzdz, mut dcdz) = (Complex64::zero(), Complex64::zero());
+        for _ in 0..period {
+            dcdz = 2.0 * (dz * dc + w * dcdz);
+            dzdz = 2.0 * (dz * dz + w * dzdz);
+            dc = 2.0 * w * dc + 1.0;
+            dz = 2.0 * w * dz;
+            w = w * w + c;
+        }
+
+        // Only attracting cycles belong to the interior
+        if dz.norm_sqr() >= 1.0 || (w - z0).norm() > CYCLE_TOLERANCE {
+            return None;
+        }
+
+        let distance = (1.0 - dz.norm_sqr()) / (dcdz + dzdz * dc / (1.0 - dz)).norm();
+
+        Some(Cycle {
+            period,
+            distance: distance / pixel_size,
+        })
+    }
+}
+
+/// Running state of an `OrbitStatistic` for a single orbit
+#[derive(Copy, Clone, Debug)]
+pub struct OrbitAccumulator {
+    statistic: OrbitStatistic,
+    /// Magnitude of the point that is iterated
+    c_norm: f64,
+    /// Last two values of the orbit, the most recent one first
+    previous: [Complex64; 2],
+    /// Number of values of the orbit so far, including `Z(0) = 0`
+    count: u32,
+    /// Sum of the terms of averaging statistics
+    sum: f64,
+    /// Last term that was added to `sum`
+    last: f64,
+    /// Number of terms added to `sum`
+    terms: u32,
+    /// Closest distance to the trap so far
+    closest: f64,
+}
+
+impl OrbitStatistic {
+    /// Whether this is the closest distance to a trap instead of an average
+    pub fn is_trap(&self) -> bool {
+        matches!(
+            self,
+            OrbitStatistic::PointTrap { .. }
+                | OrbitStatistic::LineTrap { .. }
+                | OrbitStatistic::CrossTrap { .. }
+        )
+    }
+}
+
+impl OrbitAccumulator {
+    /// Starts accumulating the orbit of `c` from `Z(0) = z`
+    pub fn new(statistic: OrbitStatistic, z: Complex64, c: Complex64) -> Self {
+        OrbitAccumulator {
+            statistic,
+            c_norm: c.norm(),
+            previous: [z, Complex64::new(0.0, 0.0)],
+            count: 1,
+            sum: 0.0,
+            last: 0.0,
+            terms: 0,
+            closest: f64::INFINITY,
+        }
+    }
+
+    /// Continues the accumulation for a different point `c`
+    ///
+    /// This is used for points that share the first iterations with a reference point.
+    pub fn with_c(self, c: Complex64) -> Self {
+        OrbitAccumulator {
+            c_norm: c.norm(),
+            ..self
+        }
+    }
+
+    /// Adds the next value of the orbit
+    #[inline]
+    pub fn add(&mut self, z: Complex64) {
+        let [z1, z2] = self.previous;
+
+        let term = match self.statistic {
+            OrbitStatistic::TriangleInequality if self.count >= 2 => {
+                let z1_norm_sqr = z1.norm_sqr();
+                let lower = (z1_norm_sqr - self.c_norm).abs();
+                let upper = z1_norm_sqr + self.c_norm;
+                (upper > lower).then(|| (z.norm() - lower) / (upper - lower))
+            }
+            OrbitStatistic::Stripe { density } => Some(0.5 + 0.5 * (density * z.arg()).sin()),
+            OrbitStatistic::Curvature if self.count >= 3 => {
+                let step = z1 - z2;
+                (step.norm_sqr() > 0.0).then(|| ((z - z1) / step).arg().abs() / PI)
+            }
+            OrbitStatistic::PointTrap { center } => {
+                self.closest = f64::min(self.closest, (z - center).norm());
+                None
+            }
+            OrbitStatistic::LineTrap { center, angle } => {
+                let z = (z - center) * Complex64::from_polar(1.0, -angle);
+                self.closest = f64::min(self.closest, z.im.abs());
+                None
+            }
+            OrbitStatistic::CrossTrap { center, angle } => {
+                let z = (z - center) * Complex64::from_polar(1.0, -angle);
+                self.closest = f64::min(self.closest, f64::min(z.re.abs(), z.im.abs()));
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(term) = term {
+            self.sum += term;
+            self.last = term;
+            self.terms += 1;
+        }
+        self.previous = [z, z1];
+        self.count += 1;
+    }
+
+    /// Final value of the statistic, where `z` is the last value of the orbit
+    pub fn value(&self, z: Complex64) -> f64 {
+        match self.statistic {
+            statistic if statistic.is_trap() => self.closest,
+            _ if self.terms < 2 => self.sum,
+            _ => {
+                // Blend between the averages with and without the last term depending on how
+                // far the orbit escaped, otherwise the value jumps between iteration bands
+                let blend = if z.norm_sqr() >= BAILOUT {
+                    let radius = BAILOUT.sqrt();
+                    (1.0 + (radius.ln() / z.norm().ln()).log2()).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+
+                let average = self.sum / self.terms as f64;
+                let previous_average = (self.sum - self.last) / (self.terms - 1) as f64;
+                previous_average + blend * (average - previous_average)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_periods() {
+        // Centers of hyperbolic components and a point close to the boundary of the main
+        // cardioid, which takes many iterations to converge
+        for &(c, period) in &[
+            (Complex64::new(0.0, 0.0), 1),
+            (Complex64::new(-1.0, 0.0), 2),
+            (Complex64::new(-0.12256116687665362, 0.7448617666197442), 3),
+            (Complex64::new(-1.7548776662466927, 0.0), 3),
+            (Complex64::new(0.24, 0.0), 1),
+        ] {
+            let mut z = Complex64::zero();
+            for _ in 0..10_000 {
+                z = z * z + c;
+            }
+
+            let cycle = Cycle::find(z, c, 1.0, 10_000).unwrap();
+            assert_eq!(cycle.period, period, "c = {}", c);
+            assert!(cycle.distance > 0.0 && cycle.distance.is_finite());
+        }
+
+        // The distance to the boundary of the main cardioid at 0.25 is estimated within a
+        // factor of four
+        let cycle = Cycle::find(Complex64::zero(), Complex64::zero(), 0.01, 100).unwrap();
+        assert!((25.0 / 4.0..=25.0 * 4.0).contains(&cycle.distance));
+    }
+
+    #[test]
+    fn cycle_not_found() {
+        // Cycles longer than the limit are not found
+        let c = Complex64::new(-1.7548776662466927, 0.0);
+        assert!(Cycle::find(Complex64::zero(), c, 1.0, 2).is_none());
+        assert!(Cycle::find(Complex64::new(0.3, 0.3), c, 1.0, 2).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+use gtk::glib;
+
+use once_cell::sync::Lazy;
+
+use std::fmt;
+use std::path::Path;
+
+use super::color::{Interpolation, Pixel};
+
+mod ggr;
+mod map;
+mod ugr;
+
+/// Color gradient that escaped points are colored with
+///
+/// The gradient is cyclic: after the last stop it continues with the first one again.
+#[derive(Clone, Debug, PartialEq, glib::Boxed)]
+#[boxed_type(name = "MandelbrotPalette")]
+pub struct Palette {
+    name: String,
+    /// Stops sorted by their position
+    stops: Vec<Stop>,
+}
+
+/// Color at a position of a gradient
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stop {
+    /// Position in the gradient, between 0.0 and 1.0
+    pub position: f64,
+    pub color: [u8; 3],
+}
+
+/// Error when loading palettes from a file
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    /// The file extension is not one of the supported formats
+    UnknownFormat,
+    /// The file has no colors at all
+    Empty,
+    /// The file is malformed at the given line, starting at 1
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+static BUILTIN: Lazy<Vec<Palette>> = Lazy::new(|| {
+    let palette = |name, stops: &[(f64, [u8; 3])]| {
+        Palette::new(
+            name,
+            stops
+                .iter()
+                .map(|&(position, color)| Stop { position, color })
+                .collect(),
+        )
+    };
+
+    vec![
+        palette(
+            "Rainbow",
+            &[
+                (0.0, [255, 0, 0]),
+                (1.0 / 6.0, [255, 255, 0]),
+                (2.0 / 6.0, [0, 255, 0]),
+                (3.0 / 6.0, [0, 255, 255]),
+                (4.0 / 6.0, [0, 0, 255]),
+                (5.0 / 6.0, [255, 0, 255]),
+            ],
+        ),
+        palette(
+            "Classic",
+            &[
+                (0.0, [0, 7, 100]),
+                (0.16, [32, 107, 203]),
+                (0.42, [237, 255, 255]),
+                (0.6425, [255, 170, 0]),
+                (0.8575, [0, 2, 0]),
+            ],
+        ),
+        palette(
+            "Fire",
+            &[
+                (0.0, [0, 0, 0]),
+                (0.25, [160, 0, 0]),
+                (0.45, [255, 96, 0]),
+                (0.65, [255, 210, 40]),
+                (0.8, [255, 255, 220]),
+            ],
+        ),
+        palette(
+            "Ocean",
+            &[
+                (0.0, [0, 10, 40]),
+                (0.35, [0, 90, 140]),
+                (0.6, [40, 190, 200]),
+                (0.8, [230, 250, 255]),
+            ],
+        ),
+        palette("Grayscale", &[(0.0, [0, 0, 0]), (0.5, [255, 255, 255])]),
+    ]
+});
+
+impl Palette {
+    /// Creates a palette from `stops`, which must not be empty
+    pub fn new(name: &str, mut stops: Vec<Stop>) -> Self {
+        assert!(!stops.is_empty(), "palette without stops");
+
+        for stop in &mut stops {
+            stop.position = stop.position.clamp(0.0, 1.0);
+        }
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+        Palette {
+            name: name.to_string(),
+            stops,
+        }
+    }
+
+    /// Palettes that are always available
+    pub fn builtin() -> &'static [Palette] {
+        &BUILTIN
+    }
+
+    /// Loads all palettes from a Fractint `.map`, GIMP `.ggr` or UltraFractal `.ugr` file
+    pub fn load(path: &Path) -> Result<Vec<Palette>, PaletteError> {
+        let contents = std::fs::read_to_string(path).map_err(PaletteError::Io)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("map") => map::parse(&name, &contents).map(|palette| vec![palette]),
+            Some("ggr") => ggr::parse(&contents).map(|palette| vec![palette]),
+            Some("ugr") => ugr::parse(&contents),
+            _ => Err(PaletteError::UnknownFormat),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Color at `position` in the gradient, between 0.0 and 1.0
+    pub(super) fn color_at(&self, position: f64, interpolation: Interpolation) -> Pixel {
+        let stops = &self.stops;
+
+        // Interpolate between the stops around `position`, wrapping around at both ends
+        let next = stops.partition_point(|stop| stop.position <= position);
+        let (a, b) = (
+            stops[(next + stops.len() - 1) % stops.len()],
+            stops[next % stops.len()],
+        );
+        let start = if next == 0 {
+            a.position - 1.0
+        } else {
+            a.position
+        };
+        let end = if next == stops.len() {
+            b.position + 1.0
+        } else {
+            b.position
+        };
+
+        let frac = if end > start {
+            (position - start) / (end - start)
+        } else {
+            0.0
+        };
+
+        Pixel::interpolate(
+            Pixel::from(a.color),
+            Pixel::from(b.color),
+            frac,
+            interpolation,
+        )
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin()[0].clone()
+    }
+}
+
+impl PaletteError {
+    fn parse(line: usize, message: impl Into<String>) -> Self {
+        PaletteError::Parse {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "Failed to read file: {err}"),
+            PaletteError::UnknownFormat => write!(
+                f,
+                "Unknown palette format, expected a .map, .ggr or .ugr file"
+            ),
+            PaletteError::Empty => write!(f, "File contains no colors"),
+            PaletteError::Parse { line, message } => write!(f, "Line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PaletteError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load() {
+        let directory =
+            std::env::temp_dir().join(format!("mandelbrot-palettes-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let load = |file: &str, contents: &str| {
+            let path = directory.join(file);
+            std::fs::write(&path, contents).unwrap();
+            Palette::load(&path)
+        };
+
+        let palettes = load("Colors.MAP", "255 0 0\n0 0 255\n").unwrap();
+        assert_eq!(palettes.len(), 1);
+        assert_eq!(palettes[0].name(), "Colors");
+
+        let palettes = load(
+            "gradient.ggr",
+            "GIMP Gradient\nName: Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n",
+        )
+        .unwrap();
+        assert_eq!(palettes[0].name(), "Gradient");
+
+        let palettes = load(
+            "collection.ugr",
+            "a {\ngradient: index=0 color=0\n}\nb {\ngradient: index=0 color=0\n}\n",
+        )
+        .unwrap();
+        assert_eq!(palettes.len(), 2);
+
+        // The contents are only parsed as the format of the extension
+        assert!(matches!(
+            load("colors.ggr", "255 0 0\n"),
+            Err(PaletteError::Parse { line: 1, .. })
+        ));
+        for file in ["colors.txt", "colors"] {
+            assert!(matches!(
+                load(file, "255 0 0\n"),
+                Err(PaletteError::UnknownFormat)
+            ));
+        }
+        assert!(matches!(
+            Palette::load(&directory.join("missing.map")),
+            Err(PaletteError::Io(_))
+        ));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn wrap_around() {
+        let stop = |position, color| Stop { position, color };
+        let palette = Palette::new(
+            "test",
+            vec![stop(0.75, [0, 0, 200]), stop(0.25, [200, 0, 0])],
+        );
+        let color_at = |position| palette.color_at(position, Interpolation::Srgb);
+
+        assert_eq!(color_at(0.25), Pixel::new(200, 0, 0));
+        assert_eq!(color_at(0.5), Pixel::new(100, 0, 100));
+        assert_eq!(color_at(0.75), Pixel::new(0, 0, 200));
+        // Between the last and the first stop, across both ends of the gradient
+        assert_eq!(color_at(0.875), Pixel::new(50, 0, 150));
+        assert_eq!(color_at(0.0), Pixel::new(100, 0, 100));
+        assert_eq!(color_at(0.125), Pixel::new(150, 0, 50));
+        assert_eq!(color_at(1.0), color_at(0.0));
+
+        // A single stop has the same color everywhere
+        let palette = Palette::new("single", vec![stop(0.5, [1, 2, 3])]);
+        for position in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(
+                palette.color_at(position, Interpolation::Oklab),
+                Pixel::new(1, 2, 3)
+            );
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! GIMP `.ggr` gradients
+//!
+//! After the `GIMP Gradient` header and an optional `Name:` line follows the number of segments
+//! and one line per segment with its left, middle and right position, the RGBA colors at its
+//! left and right end, and optionally how to blend between them and in which color space.
+
+use super::{Palette, PaletteError, Stop};
+
+use std::f64::consts::PI;
+
+/// Number of stops each segment is sampled with
+const SAMPLES_PER_SEGMENT: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Blend {
+    Linear,
+    Curved,
+    Sine,
+    SphereIncreasing,
+    SphereDecreasing,
+    Step,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ColorSpace {
+    Rgb,
+    HsvCounterClockwise,
+    HsvClockwise,
+}
+
+struct Segment {
+    left: f64,
+    middle: f64,
+    right: f64,
+    left_color: [f64; 3],
+    right_color: [f64; 3],
+    blend: Blend,
+    color_space: ColorSpace,
+}
+
+pub(super) fn parse(contents: &str) -> Result<Palette, PaletteError> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    let header_line_number = match lines.next() {
+        Some((line_number, "GIMP Gradient")) => line_number,
+        Some((line_number, _)) => {
+            return Err(PaletteError::parse(
+                line_number,
+                "Expected 'GIMP Gradient' header",
+            ))
+        }
+        None => return Err(PaletteError::Empty),
+    };
+
+    let (mut line_number, mut line) = lines
+        .next()
+        .ok_or_else(|| PaletteError::parse(header_line_number, "Expected number of segments"))?;
+
+    let mut name = "GIMP Gradient";
+    if let Some(value) = line.strip_prefix("Name:") {
+        name = value.trim();
+        (line_number, line) = lines
+            .next()
+            .ok_or_else(|| PaletteError::parse(line_number, "Expected number of segments"))?;
+    }
+
+    let count = line.parse::<usize>().map_err(|_| {
+        PaletteError::parse(line_number, format!("Invalid number of segments '{line}'"))
+    })?;
+
+    let mut segments = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (line_number, line) = lines.next().ok_or_else(|| {
+            PaletteError::parse(
+                line_number,
+                format!(
+                    "Expected {count} segments but found only {}",
+                    segments.len()
+                ),
+            )
+        })?;
+        segments.push(parse_segment(line_number, line)?);
+    }
+
+    if segments.is_empty() {
+        return Err(PaletteError::Empty);
+    }
+
+    let stops = segments.iter().flat_map(Segment::stops).collect();
+
+    Ok(Palette::new(name, stops))
+}
+
+fn parse_segment(line_number: usize, line: &str) -> Result<Segment, PaletteError> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() < 11 {
+        return Err(PaletteError::parse(
+            line_number,
+            format!(
+                "Expected at least 11 values per segment, found {}",
+                fields.len()
+            ),
+        ));
+    }
+
+    let number = |index: usize| {
+        fields[index].parse::<f64>().map_err(|_| {
+            PaletteError::parse(line_number, format!("Invalid number '{}'", fields[index]))
+        })
+    };
+    let color = |index: usize| -> Result<[f64; 3], PaletteError> {
+        Ok([
+            number(index)?.clamp(0.0, 1.0),
+            number(index + 1)?.clamp(0.0, 1.0),
+            number(index + 2)?.clamp(0.0, 1.0),
+        ])
+    };
+
+    let left = number(0)?.clamp(0.0, 1.0);
+    let right = number(2)?.clamp(left, 1.0);
+    let middle = number(1)?.clamp(left, right);
+
+    let blend = match fields.get(11).copied().unwrap_or("0") {
+        "0" => Blend::Linear,
+        "1" => Blend::Curved,
+        "2" => Blend::Sine,
+        "3" => Blend::SphereIncreasing,
+        "4" => Blend::SphereDecreasing,
+        "5" => Blend::Step,
+        blend => {
+            return Err(PaletteError::parse(
+                line_number,
+                format!("Unknown blend function '{blend}'"),
+            ))
+        }
+    };
+    let color_space = match fields.get(12).copied().unwrap_or("0") {
+        "0" => ColorSpace::Rgb,
+        "1" => ColorSpace::HsvCounterClockwise,
+        "2" => ColorSpace::HsvClockwise,
+        color_space => {
+            return Err(PaletteError::parse(
+                line_number,
+                format!("Unknown color type '{color_space}'"),
+            ))
+        }
+    };
+
+    // Alpha of both ends is ignored, the image is always opaque
+    Ok(Segment {
+        left,
+        middle,
+        right,
+        left_color: color(3)?,
+        right_color: color(7)?,
+        blend,
+        color_space,
+    })
+}
+
+impl Segment {
+    /// Samples the segment into stops, including both of its ends
+    fn stops(&self) -> Vec<Stop> {
+        let width = self.right - self.left;
+
+        // Steps are not sampled but exactly placed at the middle
+        if self.blend == Blend::Step || width <= f64::EPSILON {
+            let middle = if width <= f64::EPSILON {
+                self.left
+            } else {
+                self.middle
+            };
+            return vec![
+                self.stop(self.left, 0.0),
+                self.stop(middle, 0.0),
+                self.stop(middle, 1.0),
+                self.stop(self.right, 1.0),
+            ];
+        }
+
+        let middle = (self.middle - self.left) / width;
+        (0..SAMPLES_PER_SEGMENT)
+            .map(|i| i as f64 / (SAMPLES_PER_SEGMENT - 1) as f64)
+            .map(|position| self.stop(self.left + position * width, self.factor(position, middle)))
+            .collect()
+    }
+
+    /// Blend factor between the left and right color at `position` within the segment
+    fn factor(&self, position: f64, middle: f64) -> f64 {
+        let linear = || {
+            if position <= middle {
+                if middle < f64::EPSILON {
+                    0.0
+                } else {
+                    0.5 * position / middle
+                }
+            } else if middle > 1.0 - f64::EPSILON {
+                1.0
+            } else {
+                0.5 + 0.5 * (position - middle) / (1.0 - middle)
+            }
+        };
+
+        match self.blend {
+            Blend::Linear => linear(),
+            Blend::Curved => {
+                if middle < f64::EPSILON {
+                    1.0
+                } else {
+                    position.powf(0.5f64.ln() / middle.ln())
+                }
+            }
+            Blend::Sine => ((-PI / 2.0 + PI * linear()).sin() + 1.0) / 2.0,
+            Blend::SphereIncreasing => (1.0 - (linear() - 1.0).powi(2)).sqrt(),
+            Blend::SphereDecreasing => 1.0 - (1.0 - linear().powi(2)).sqrt(),
+            Blend::Step => {
+                if position >= middle {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    fn stop(&self, position: f64, factor: f64) -> Stop {
+        let [r, g, b] = match self.color_space {
+            ColorSpace::Rgb => std::array::from_fn(|i| {
+                self.left_color[i] + (self.right_color[i] - self.left_color[i]) * factor
+            }),
+            ColorSpace::HsvCounterClockwise | ColorSpace::HsvClockwise => {
+                let [h0, s0, v0] = rgb_to_hsv(self.left_color);
+                let [h1, s1, v1] = rgb_to_hsv(self.right_color);
+
+                let h = if self.color_space == ColorSpace::HsvCounterClockwise {
+                    if h0 < h1 {
+                        h0 + (h1 - h0) * factor
+                    } else {
+                        h0 + (1.0 - (h0 - h1)) * factor
+                    }
+                } else if h1 < h0 {
+                    h0 - (h0 - h1) * factor
+                } else {
+                    h0 - (1.0 - (h1 - h0)) * factor
+                };
+
+                hsv_to_rgb([
+                    h.rem_euclid(1.0),
+                    s0 + (s1 - s0) * factor,
+                    v0 + (v1 - v0) * factor,
+                ])
+            }
+        };
+
+        Stop {
+            position,
+            color: [r, g, b].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+        }
+    }
+}
+
+/// Converts RGB to hue, saturation and value, all between 0.0 and 1.0
+fn rgb_to_hsv([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let s = if max <= 0.0 { 0.0 } else { delta / max };
+
+    [h, s, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f64; 3]) -> [f64; 3] {
+    let h = h * 6.0;
+    let sector = h.floor();
+    let f = h - sector;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+
+    match sector as u32 % 6 {
+        0 => [v, t, p],
+        1 => [q, v, p],
+        2 => [p, v, t],
+        3 => [p, q, v],
+        4 => [t, p, v],
+        _ => [v, p, q],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line number of the parse error of `contents`
+    fn error_line(contents: &str) -> usize {
+        match parse(contents) {
+            Err(PaletteError::Parse { line, .. }) => line,
+            result => panic!("Expected parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parse_gradient() {
+        let palette = parse(
+            "GIMP Gradient\n\
+             Name: Black to white\n\
+             1\n\
+             0.0 0.5 1.0 0 0 0 1 1 1 1 1 0 0\n",
+        )
+        .unwrap();
+
+        assert_eq!(palette.name(), "Black to white");
+        assert_eq!(palette.stops.len(), SAMPLES_PER_SEGMENT);
+        assert_eq!(palette.stops[0].color, [0, 0, 0]);
+        assert_eq!(
+            palette.stops[SAMPLES_PER_SEGMENT - 1].color,
+            [255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn error_line_numbers() {
+        assert_eq!(error_line("\n\nGIMP Gradient\n"), 3);
+        assert_eq!(error_line("GIMP Gradient\n\nName: Empty\n"), 3);
+        assert_eq!(error_line("GIMP Gradient\nName: Invalid\n\nx\n"), 4);
+        assert_eq!(error_line("GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1\n"), 2);
+        assert_eq!(error_line("GIMP Gradient\n1\n\n0 0.5 1 0 0 0 1\n"), 4);
+        assert_eq!(error_line("Gradient\n"), 1);
+    }
+}
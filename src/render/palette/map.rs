@@ -0,0 +1,108 @@
+//! Fractint `.map` palettes
+//!
+//! Every line contains the red, green and blue components of one color as decimal numbers
+//! between 0 and 255. Everything after the third number is a comment.
+
+use super::{Palette, PaletteError, Stop};
+
+pub(super) fn parse(name: &str, contents: &str) -> Result<Palette, PaletteError> {
+    let mut colors = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut components = line.split_whitespace();
+        let mut color = [0u8; 3];
+        for component in &mut color {
+            let value = components.next().ok_or_else(|| {
+                PaletteError::parse(line_number, "Expected red, green and blue components")
+            })?;
+            *component = value.parse().map_err(|_| {
+                PaletteError::parse(
+                    line_number,
+                    format!("Invalid color component '{value}', expected a number from 0 to 255"),
+                )
+            })?;
+        }
+
+        colors.push(color);
+    }
+
+    if colors.is_empty() {
+        return Err(PaletteError::Empty);
+    }
+
+    let count = colors.len();
+    let stops = colors
+        .into_iter()
+        .enumerate()
+        .map(|(index, color)| Stop {
+            position: index as f64 / count as f64,
+            color,
+        })
+        .collect();
+
+    Ok(Palette::new(name, stops))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line number and message of the parse error of `contents`
+    fn error(contents: &str) -> (usize, String) {
+        match parse("test", contents) {
+            Err(PaletteError::Parse { line, message }) => (line, message),
+            result => panic!("Expected parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parse_colors() {
+        let palette = parse("test", "0 0 0 black\n\n255 128 1\n").unwrap();
+
+        assert_eq!(palette.name(), "test");
+        assert_eq!(
+            palette.stops,
+            [
+                Stop {
+                    position: 0.0,
+                    color: [0, 0, 0]
+                },
+                Stop {
+                    position: 0.5,
+                    color: [255, 128, 1]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("0 0 0\n1 2\n"),
+            (2, "Expected red, green and blue components".to_string())
+        );
+        assert_eq!(
+            error("0 0 0\n\n255 256 0\n"),
+            (
+                3,
+                "Invalid color component '256', expected a number from 0 to 255".to_string()
+            )
+        );
+        assert_eq!(
+            error("red green blue\n"),
+            (
+                1,
+                "Invalid color component 'red', expected a number from 0 to 255".to_string()
+            )
+        );
+
+        for contents in ["", "\n  \n"] {
+            assert!(matches!(parse("test", contents), Err(PaletteError::Empty)));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! UltraFractal `.ugr` gradient collections
+//!
+//! A file contains any number of entries of the form `name { ... }`. The `gradient:` section of
+//! an entry has a `title` and one `index=N color=C` pair per stop, where the index is between 0
+//! and 399 and the color is a decimal `0xBBGGRR` value. Lines starting with `;` are comments.
+
+use super::{Palette, PaletteError, Stop};
+
+/// Length of UltraFractal gradients in index units
+const GRADIENT_LENGTH: i64 = 400;
+
+pub(super) fn parse(contents: &str) -> Result<Vec<Palette>, PaletteError> {
+    let mut palettes = Vec::new();
+    // Name, line number where it started, whether the gradient section is active, stops
+    let mut entry: Option<(String, usize, bool, Vec<Stop>)> = None;
+    let mut index = None;
+
+    for (line_index, line) in contents.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let Some((name, start, in_gradient, stops)) = entry.as_mut() else {
+            let Some(name) = line.strip_suffix('{') else {
+                return Err(PaletteError::parse(
+                    line_number,
+                    "Expected the start of a gradient entry",
+                ));
+            };
+            entry = Some((name.trim().to_string(), line_number, false, Vec::new()));
+            continue;
+        };
+
+        if line == "}" {
+            if stops.is_empty() {
+                return Err(PaletteError::parse(
+                    *start,
+                    format!("Gradient '{name}' has no colors"),
+                ));
+            }
+            palettes.push(Palette::new(name, std::mem::take(stops)));
+            entry = None;
+            index = None;
+            continue;
+        }
+
+        for token in tokens(line) {
+            if let Some(section) = token.strip_suffix(':') {
+                *in_gradient = section == "gradient";
+                continue;
+            }
+            if !*in_gradient {
+                continue;
+            }
+
+            let Some((key, value)) = token.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_matches('"');
+            match key {
+                "title" => *name = value.to_string(),
+                "index" => {
+                    let value = value.parse::<i64>().map_err(|_| {
+                        PaletteError::parse(line_number, format!("Invalid index '{value}'"))
+                    })?;
+                    index = Some(value);
+                }
+                "color" => {
+                    let Some(index) = index.take() else {
+                        return Err(PaletteError::parse(
+                            line_number,
+                            "Color without preceding index",
+                        ));
+                    };
+                    let color = value.parse::<u32>().map_err(|_| {
+                        PaletteError::parse(line_number, format!("Invalid color '{value}'"))
+                    })?;
+                    stops.push(Stop {
+                        position: index.rem_euclid(GRADIENT_LENGTH) as f64 / GRADIENT_LENGTH as f64,
+                        color: [
+                            (color & 0xff) as u8,
+                            ((color >> 8) & 0xff) as u8,
+                            ((color >> 16) & 0xff) as u8,
+                        ],
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if let Some((name, start, ..)) = entry {
+        return Err(PaletteError::parse(
+            start,
+            format!("Gradient '{name}' is not terminated with '}}'"),
+        ));
+    }
+
+    if palettes.is_empty() {
+        return Err(PaletteError::Empty);
+    }
+
+    Ok(palettes)
+}
+
+/// Splits `line` at whitespace outside of quotes
+fn tokens(line: &str) -> impl Iterator<Item = &str> {
+    let mut rest = line;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut quoted = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c.is_whitespace() && !quoted
+            })
+            .map_or(rest.len(), |(end, _)| end);
+
+        let (token, remainder) = rest.split_at(end);
+        rest = remainder;
+        Some(token)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line number and message of the parse error of `contents`
+    fn error(contents: &str) -> (usize, String) {
+        match parse(contents) {
+            Err(PaletteError::Parse { line, message }) => (line, message),
+            result => panic!("Expected parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parse_entries() {
+        let palettes = parse(
+            "; Two gradients\n\
+             first {\n\
+             gradient:\n  \
+             title=\"a b\" smooth=yes\n  \
+             index=0 color=255\n  \
+             index=200 color=16711680\n\
+             opacity:\n  \
+             index=100 color=65280\n\
+             }\n\
+             \n\
+             second {\n\
+             gradient: title=second index=-100 color=65280\n\
+             }\n",
+        )
+        .unwrap();
+
+        assert_eq!(palettes.len(), 2);
+        // Colors are stored as 0xBBGGRR
+        assert_eq!(palettes[0].name(), "a b");
+        assert_eq!(
+            palettes[0].stops,
+            [
+                Stop {
+                    position: 0.0,
+                    color: [255, 0, 0]
+                },
+                Stop {
+                    position: 0.5,
+                    color: [0, 0, 255]
+                },
+            ]
+        );
+        assert_eq!(palettes[1].name(), "second");
+        assert_eq!(
+            palettes[1].stops,
+            [Stop {
+                position: 0.75,
+                color: [0, 255, 0]
+            }]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("entry {\ngradient:\ncolor=255\n}\n"),
+            (3, "Color without preceding index".to_string())
+        );
+        assert_eq!(
+            error("entry {\ngradient:\nindex=0 color=255\n"),
+            (1, "Gradient 'entry' is not terminated with '}'".to_string())
+        );
+        assert_eq!(
+            error("; Comment\n\nentry {\ngradient: title=empty\n}\n"),
+            (3, "Gradient 'empty' has no colors".to_string())
+        );
+        assert_eq!(
+            error("; Comment\ngradient:\nentry {\n}\n"),
+            (2, "Expected the start of a gradient entry".to_string())
+        );
+        assert!(matches!(parse("; Comment\n"), Err(PaletteError::Empty)));
+    }
+}
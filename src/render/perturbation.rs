@@ -0,0 +1,667 @@
+use num_complex::{Complex, Complex64};
+use num_traits::{Num, Zero};
+
+use rayon::prelude::*;
+
+use super::bigfixed::BigFixed;
+use super::floatexp::FloatExp;
+use super::orbit::{OrbitAccumulator, Tracking, TrackingOptions};
+use super::{CancellationToken, View, BAILOUT};
+
+/// Number type the differences to the reference orbit are computed with
+///
+/// `f64` is used as long as the pixel size is within its range, and `FloatExp` beyond that.
+pub trait Delta: Copy + Num + PartialOrd + Send + Sync {
+    fn from_float_exp(value: FloatExp) -> Self;
+    fn to_f64(self) -> f64;
+    fn is_finite(self) -> bool;
+
+    /// Continues iterating the point `dc` away from the reference point from iteration
+    /// `start`, where the difference to the reference orbit is `dz`
+    ///
+    /// The quantities in `tracking` are updated along with the orbit and returned, see
+    /// `Perturbation::iterate`.
+    fn iterate_from(
+        reference: &Reference,
+        dc: Complex<Self>,
+        dz: Complex<Self>,
+        tracking: Tracking<Self>,
+        start: u32,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>);
+
+    fn from_f64(value: f64) -> Self {
+        Self::from_float_exp(FloatExp::from_f64(value))
+    }
+}
+
+impl Delta for f64 {
+    fn from_float_exp(value: FloatExp) -> Self {
+        value.to_f64()
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+
+    fn iterate_from(
+        reference: &Reference,
+        dc: Complex<Self>,
+        dz: Complex<Self>,
+        tracking: Tracking<Self>,
+        start: u32,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>) {
+        reference.iterate_from(dc, dz, tracking, start as usize, start, max_iterations)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+impl Delta for FloatExp {
+    fn from_float_exp(value: FloatExp) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        FloatExp::to_f64(self)
+    }
+
+    fn is_finite(self) -> bool {
+        FloatExp::is_finite(self)
+    }
+
+    fn iterate_from(
+        reference: &Reference,
+        dc: Complex<Self>,
+        dz: Complex<Self>,
+        tracking: Tracking<Self>,
+        start: u32,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>) {
+        reference.iterate_from_float_exp(dc, dz, tracking, start, max_iterations)
+    }
+}
+
+fn to_delta<T: Delta>(z: Complex64) -> Complex<T> {
+    Complex::new(T::from_f64(z.re), T::from_f64(z.im))
+}
+
+fn to_f64<T: Delta>(z: Complex<T>) -> Complex64 {
+    Complex64::new(z.re.to_f64(), z.im.to_f64())
+}
+
+fn tracking_to_f64<T: Delta>(tracking: Tracking<T>) -> Tracking<f64> {
+    Tracking {
+        derivative: tracking.derivative.map(to_f64),
+        statistic: tracking.statistic,
+        atom_domain: tracking.atom_domain,
+    }
+}
+
+/// Renders a view relative to a high precision reference orbit
+#[derive(Debug)]
+pub struct Perturbation<T> {
+    reference: Reference,
+    series: Option<SeriesApproximation<T>>,
+    /// Center of the view
+    center: Complex64,
+    /// Size of the view
+    size: Complex<T>,
+    /// What is tracked along the orbit of every point
+    options: TrackingOptions,
+    /// Statistic of the iterations skipped by the series approximation
+    ///
+    /// The orbits of all points are nearly identical to the reference orbit during these
+    /// iterations, so the statistic of the reference orbit is used for all of them. Only
+    /// averages are approximated like this, iterations are never skipped for traps.
+    series_statistic: Option<OrbitAccumulator>,
+}
+
+impl<T: Delta> Perturbation<T> {
+    /// Returns `None` if rendering was cancelled while computing the reference orbit
+    pub fn new(
+        view: &View,
+        pixel_size: FloatExp,
+        max_iterations: u32,
+        options: TrackingOptions,
+        cancel: &CancellationToken,
+    ) -> Option<Self> {
+        let size = Complex::new(
+            T::from_float_exp(view.width()),
+            T::from_float_exp(view.height()),
+        );
+
+        let reference = find_reference(view, size, pixel_size, max_iterations, cancel)?;
+
+        // Probe the corners of the view for the series approximation
+        let probes = [(-0.5, -0.5), (0.5, -0.5), (-0.5, 0.5), (0.5, 0.5)]
+            .iter()
+            .map(|(x, y)| delta(Complex64::new(*x, *y) - reference.position, size))
+            .collect::<Vec<_>>();
+        let series = if options.allows_skipping() {
+            SeriesApproximation::new(&reference, &probes, T::from_float_exp(pixel_size))
+        } else {
+            None
+        };
+
+        let center = Complex64::new(view.center_x().to_f64(), view.center_y().to_f64());
+        let series_statistic = options
+            .statistic
+            .zip(series.as_ref())
+            .map(|(statistic, series)| {
+                let mut accumulator = OrbitAccumulator::new(statistic, Complex64::zero(), center);
+                for z in &reference.orbit[1..=series.skip as usize] {
+                    accumulator.add(*z);
+                }
+                accumulator
+            });
+
+        Some(Perturbation {
+            reference,
+            series,
+            center,
+            size,
+            options,
+            series_statistic,
+        })
+    }
+
+    /// Point at `position` relative to the view center, in units of the view size
+    ///
+    /// This is only as precise as `f64`.
+    pub fn point(&self, position: Complex64) -> Complex64 {
+        self.center + to_f64(delta(position, self.size))
+    }
+
+    /// Iterates the point at `position` relative to the view center, in units of the
+    /// view size
+    ///
+    /// Returns the number of iterations and the last value of the orbit, like
+    /// iterating the point directly would, and the quantities that were tracked according to
+    /// the options given when creating the perturbation. The derivative of the last value
+    /// with respect to `c` is in units of the pixel size.
+    pub fn iterate(
+        &self,
+        position: Complex64,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>) {
+        let dc = delta(position - self.reference.position, self.size);
+        let pixel_size = T::from_float_exp(self.reference.pixel_size);
+        let c = self.point(position);
+
+        if let Some(ref series) = self.series {
+            if let Some(dz) = series.evaluate(dc) {
+                let skip = series.skip;
+                // The point might have escaped already during the skipped iterations
+                let z = self.reference.orbit[skip as usize] + to_f64(dz);
+                if z.norm_sqr() < BAILOUT {
+                    let tracking = Tracking {
+                        derivative: self
+                            .options
+                            .derivative
+                            .then(|| series.derivative(dc) * pixel_size),
+                        statistic: self.series_statistic.map(|statistic| statistic.with_c(c)),
+                        ..Tracking::new(&self.options, Complex64::zero(), c)
+                    };
+                    return T::iterate_from(
+                        &self.reference,
+                        dc,
+                        dz,
+                        tracking,
+                        skip,
+                        max_iterations,
+                    );
+                }
+            }
+        }
+
+        T::iterate_from(
+            &self.reference,
+            dc,
+            Complex::zero(),
+            Tracking::new(&self.options, Complex64::zero(), c),
+            0,
+            max_iterations,
+        )
+    }
+}
+
+/// Difference in the complex plane for a difference of `position` in units of the view size
+fn delta<T: Delta>(position: Complex64, size: Complex<T>) -> Complex<T> {
+    Complex::new(
+        T::from_f64(position.re) * size.re,
+        T::from_f64(position.im) * size.im,
+    )
+}
+
+/// High precision orbit of a reference point that all pixels are iterated relative to
+///
+/// With perturbation theory only the orbit of the reference point `C` has to be computed with
+/// arbitrary precision. For every pixel `c = C + dc` only the difference `dz` of its orbit to
+/// the reference orbit `Z` is iterated with low precision:
+///
+/// `dz(n+1) = 2 * Z(n) * dz(n) + dz(n)² + dc`
+///
+/// The derivative `z'` of the orbit with respect to `c` is tracked in units of the pixel size
+/// so that it stays in the range of `f64` for deep zooms:
+///
+/// `z'(n+1) = 2 * z(n) * z'(n) + pixel_size`
+#[derive(Debug)]
+pub struct Reference {
+    /// Position of the reference point relative to the view center, in units of the view size
+    position: Complex64,
+    /// Size of a pixel of the view in the complex plane
+    pixel_size: FloatExp,
+    /// Orbit of the reference point, starting with `Z(0) = 0`. Contains either
+    /// `max_iterations + 1` values or ends with the first value that escaped.
+    orbit: Vec<Complex64>,
+}
+
+impl Reference {
+    /// Computes the orbit of the point at `position` in `view`
+    ///
+    /// Returns `None` if rendering was cancelled in the meantime.
+    fn new(
+        view: &View,
+        position: Complex64,
+        pixel_size: FloatExp,
+        max_iterations: u32,
+        cancel: &CancellationToken,
+    ) -> Option<Self> {
+        let precision = view.precision();
+        let offset_x = view.width() * FloatExp::from_f64(position.re);
+        let offset_y = view.height() * FloatExp::from_f64(position.im);
+        let cx = view.center_x() + &BigFixed::from_float_exp(offset_x, precision);
+        let cy = view.center_y() + &BigFixed::from_float_exp(offset_y, precision);
+
+        let mut zx = BigFixed::zero(precision);
+        let mut zy = BigFixed::zero(precision);
+
+        let mut orbit = Vec::with_capacity(max_iterations as usize + 1);
+        orbit.push(Complex64::new(0.0, 0.0));
+        for i in 0..max_iterations {
+            if i % 1024 == 0 && cancel.is_cancelled() {
+                return None;
+            }
+
+            let zxy = &zx * &zy;
+            zx = &(&zx.square() - &zy.square()) + &cx;
+            zy = &zxy.double() + &cy;
+
+            let z = Complex64::new(zx.to_f64(), zy.to_f64());
+            orbit.push(z);
+            if z.norm_sqr() >= BAILOUT {
+                break;
+            }
+        }
+
+        Some(Reference {
+            position,
+            pixel_size,
+            orbit,
+        })
+    }
+
+    /// Number of iterations until the reference point escaped, or the iteration limit
+    fn iterations(&self) -> u32 {
+        (self.orbit.len() - 1) as u32
+    }
+
+    /// Continues iterating the point `dc` away from the reference point
+    ///
+    /// `dz` is the difference to the reference orbit at index `n` after `it` iterations.
+    fn iterate_from(
+        &self,
+        dc: Complex64,
+        mut dz: Complex64,
+        mut tracking: Tracking<f64>,
+        mut n: usize,
+        mut it: u32,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>) {
+        let orbit = &self.orbit;
+        let pixel_size = self.pixel_size.to_f64();
+
+        let mut z = orbit[n] + dz;
+
+        while it < max_iterations {
+            if let Some(ref mut derivative) = tracking.derivative {
+                *derivative = 2.0 * z * *derivative + pixel_size;
+            }
+            dz = (2.0 * orbit[n] + dz) * dz + dc;
+            n += 1;
+            it += 1;
+
+            z = orbit[n] + dz;
+            if let Some(ref mut statistic) = tracking.statistic {
+                statistic.add(z);
+            }
+            if let Some(ref mut atom_domain) = tracking.atom_domain {
+                atom_domain.add(z, it);
+            }
+            if z.norm_sqr() >= BAILOUT {
+                break;
+            }
+
+            // Once the orbit gets closer to zero than to the reference orbit, the delta
+            // has a larger magnitude than the value itself and precision is lost when adding
+            // both. This shows up as glitches, so instead rebase the delta to the start of
+            // the reference orbit where `Z(0) = 0`. The same is needed if the reference
+            // orbit escaped already.
+            if z.norm_sqr() < dz.norm_sqr() || n == orbit.len() - 1 {
+                dz = z;
+                n = 0;
+            }
+        }
+
+        (it, z, tracking)
+    }
+
+    /// Like `iterate_from` but for differences that are too small for `f64`
+    ///
+    /// Once the difference has grown enough it continues with `f64`, dropping `dc` which
+    /// does not contribute anymore at that point.
+    fn iterate_from_float_exp(
+        &self,
+        dc: Complex<FloatExp>,
+        mut dz: Complex<FloatExp>,
+        mut tracking: Tracking<FloatExp>,
+        start: u32,
+        max_iterations: u32,
+    ) -> (u32, Complex64, Tracking<f64>) {
+        // |dz| > 2^-960, far enough from the smallest normal `f64` to not lose any precision
+        let threshold = FloatExp::new(1.0, -1920);
+
+        let orbit = &self.orbit;
+        let mut n = start as usize;
+        let mut it = start;
+
+        while it < max_iterations {
+            if dz.norm_sqr() > threshold {
+                return self.iterate_from(
+                    to_f64(dc),
+                    to_f64(dz),
+                    tracking_to_f64(tracking),
+                    n,
+                    it,
+                    max_iterations,
+                );
+            }
+
+            // The difference is negligible for the other tracked quantities too
+            if let Some(ref mut derivative) = tracking.derivative {
+                *derivative = to_delta::<FloatExp>(2.0 * orbit[n]) * *derivative + self.pixel_size;
+            }
+            dz = (to_delta::<FloatExp>(2.0 * orbit[n]) + dz) * dz + dc;
+            n += 1;
+            it += 1;
+
+            if let Some(ref mut statistic) = tracking.statistic {
+                statistic.add(orbit[n]);
+            }
+            if let Some(ref mut atom_domain) = tracking.atom_domain {
+                atom_domain.add(orbit[n], it);
+            }
+
+            // The difference is negligible compared to the reference orbit, so the point
+            // escapes together with the reference and never needs to be rebased
+            if orbit[n].norm_sqr() >= BAILOUT {
+                break;
+            }
+        }
+
+        (it, orbit[n] + to_f64(dz), tracking_to_f64(tracking))
+    }
+
+    /// Difference to the reference orbit after `iterations` iterations, without rebasing
+    /// and without checking for escape
+    fn delta_after<T: Delta>(&self, dc: Complex<T>, iterations: usize) -> Complex<T> {
+        self.orbit[..iterations]
+            .iter()
+            .fold(Complex::zero(), |dz, z| {
+                (to_delta::<T>(2.0 * z) + dz) * dz + dc
+            })
+    }
+}
+
+/// Approximation of the difference to the reference orbit after skipping the first iterations
+///
+/// For all points close enough to the reference point the difference after `n` iterations is
+/// approximated by a polynomial in `dc`
+///
+/// `dz(n) = A(n) * dc + B(n) * dc² + C(n) * dc³`
+///
+/// with coefficients that only depend on the reference orbit. Evaluating it replaces the
+/// first `n` iterations, which are mostly identical for all pixels of a deep zoom.
+#[derive(Debug)]
+pub struct SeriesApproximation<T> {
+    /// Number of iterations that are skipped
+    skip: u32,
+    /// Coefficients after `skip` iterations
+    coefficients: [Complex<T>; 3],
+    /// Squared largest distance to the reference point for which the approximation is valid
+    radius_sqr: T,
+    /// Squared error tolerance of the approximation
+    tolerance_sqr: T,
+}
+
+impl<T: Delta> SeriesApproximation<T> {
+    /// Tolerated error relative to the difference between neighbouring pixels
+    const TOLERANCE: f64 = 1e-3;
+
+    /// Finds the number of iterations that can be skipped for points up to `probes` away
+    /// from the reference point
+    ///
+    /// The approximation is only used if it matches the iterated orbits of all probes.
+    fn new(reference: &Reference, probes: &[Complex<T>], pixel_size: T) -> Option<Self> {
+        let radius_sqr = probes
+            .iter()
+            .map(|dc| dc.norm_sqr())
+            .fold(T::zero(), |a, b| if b > a { b } else { a });
+        let radius_6 = radius_sqr * radius_sqr * radius_sqr;
+
+        let tolerance = T::from_f64(Self::TOLERANCE) * pixel_size;
+        let tolerance_sqr = |a: Complex<T>| tolerance * tolerance * a.norm_sqr();
+
+        // Iterate the coefficients until the truncation error, estimated by the last term,
+        // becomes noticeable compared to the distance between neighbouring pixels
+        let mut coefficients = Vec::new();
+        let (mut a, mut b, mut c): (Complex<T>, Complex<T>, Complex<T>) =
+            (Complex::zero(), Complex::zero(), Complex::zero());
+        let one = Complex::new(T::one(), T::zero());
+        let two = T::from_f64(2.0);
+        for z in &reference.orbit[..reference.orbit.len() - 1] {
+            let error_sqr = c.norm_sqr() * radius_6;
+            if !(error_sqr.is_finite() && error_sqr <= tolerance_sqr(a)) {
+                break;
+            }
+            coefficients.push([a, b, c]);
+
+            let z2 = to_delta::<T>(2.0 * z);
+            let next_a = z2 * a + one;
+            let next_b = z2 * b + a * a;
+            let next_c = z2 * c + a * b * two;
+            a = next_a;
+            b = next_b;
+            c = next_c;
+        }
+
+        // Check the approximation against the actually iterated probes and skip fewer
+        // iterations until they all match
+        let mut skip = coefficients.len().checked_sub(1)?;
+        while skip > 1 {
+            let series = SeriesApproximation {
+                skip: skip as u32,
+                coefficients: coefficients[skip],
+                radius_sqr,
+                tolerance_sqr: tolerance_sqr(coefficients[skip][0]),
+            };
+
+            let matches = probes.iter().all(|dc| {
+                let dz = reference.delta_after(*dc, skip);
+                series.evaluate(*dc).is_some_and(|approximated| {
+                    (approximated - dz).norm_sqr() <= series.tolerance_sqr
+                })
+            });
+
+            if matches {
+                return Some(series);
+            }
+
+            skip /= 2;
+        }
+
+        None
+    }
+
+    /// Approximated difference to the reference orbit after the skipped iterations
+    ///
+    /// Returns `None` if the approximation is not valid for this point.
+    fn evaluate(&self, dc: Complex<T>) -> Option<Complex<T>> {
+        let [a, b, c] = self.coefficients;
+
+        let dc_norm_sqr = dc.norm_sqr();
+        let error_sqr = c.norm_sqr() * dc_norm_sqr * dc_norm_sqr * dc_norm_sqr;
+        if dc_norm_sqr > self.radius_sqr || error_sqr > self.tolerance_sqr {
+            return None;
+        }
+
+        Some(((c * dc + b) * dc + a) * dc)
+    }
+
+    /// Derivative of the approximated difference with respect to `dc`
+    fn derivative(&self, dc: Complex<T>) -> Complex<T> {
+        let [a, b, c] = self.coefficients;
+
+        (c * dc * T::from_f64(3.0) + b * T::from_f64(2.0)) * dc + a
+    }
+}
+
+/// Selects a reference point for rendering `view` of the given `size` and `pixel_size`
+///
+/// The view center is used unless it escapes. In that case every pixel that needs more
+/// iterations than the reference has to be rebased repeatedly, so a grid of points in the
+/// view is probed for one that stays bounded longer and the reference is re-computed there.
+///
+/// Returns `None` if rendering was cancelled in the meantime.
+fn find_reference<T: Delta>(
+    view: &View,
+    size: Complex<T>,
+    pixel_size: FloatExp,
+    max_iterations: u32,
+    cancel: &CancellationToken,
+) -> Option<Reference> {
+    const PROBES: usize = 16;
+
+    let reference = Reference::new(
+        view,
+        Complex64::new(0.0, 0.0),
+        pixel_size,
+        max_iterations,
+        cancel,
+    )?;
+    if reference.iterations() == max_iterations {
+        return Some(reference);
+    }
+
+    let best = (0..PROBES * PROBES)
+        .into_par_iter()
+        .map(|i| {
+            let position = Complex64::new(
+                ((i % PROBES) as f64 + 0.5) / PROBES as f64 - 0.5,
+                ((i / PROBES) as f64 + 0.5) / PROBES as f64 - 0.5,
+            );
+            let dc = delta(position - reference.position, size);
+            let (it, ..) = T::iterate_from(
+                &reference,
+                dc,
+                Complex::zero(),
+                Tracking::default(),
+                0,
+                max_iterations,
+            );
+            (it, position)
+        })
+        .max_by(|(it_a, _), (it_b, _)| it_a.cmp(it_b));
+
+    match best {
+        Some((it, position)) if it > reference.iterations() => {
+            let candidate = Reference::new(view, position, pixel_size, max_iterations, cancel)?;
+            if candidate.iterations() > reference.iterations() {
+                Some(candidate)
+            } else {
+                Some(reference)
+            }
+        }
+        _ => Some(reference),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Iteration counts of a grid of points in `view`, iterated relative to a reference orbit
+    /// with `T` and directly with `BigFixed`
+    fn iterations<T: Delta>(view: &View, max_iterations: u32) -> Vec<(u32, u32)> {
+        const POINTS: usize = 8;
+
+        let pixel_size = view.width() * FloatExp::from_f64(1.0 / 1000.0);
+        let cancel = CancellationToken::default();
+        let perturbation = Perturbation::<T>::new(
+            view,
+            pixel_size,
+            max_iterations,
+            TrackingOptions::default(),
+            &cancel,
+        )
+        .unwrap();
+
+        (0..POINTS * POINTS)
+            .map(|i| {
+                let position = Complex64::new(
+                    (i % POINTS) as f64 / POINTS as f64 - 0.5,
+                    (i / POINTS) as f64 / POINTS as f64 - 0.5,
+                );
+                let (perturbed, ..) = perturbation.iterate(position, max_iterations);
+                // The reference orbit of a point is its orbit iterated with `BigFixed`
+                let direct = Reference::new(view, position, pixel_size, max_iterations, &cancel)
+                    .unwrap()
+                    .iterations();
+                (perturbed, direct)
+            })
+            .collect()
+    }
+
+    /// Asserts that all points escape after the same number of iterations both ways, and
+    /// that not all of them do so after the same number
+    fn assert_matches_direct<T: Delta>(view: &View, max_iterations: u32) {
+        let iterations = iterations::<T>(view, max_iterations);
+
+        for &(perturbed, direct) in &iterations {
+            assert_eq!(perturbed, direct);
+        }
+        assert!(iterations
+            .iter()
+            .any(|&(perturbed, _)| perturbed != iterations[0].0));
+    }
+
+    #[test]
+    fn matches_direct_iteration() {
+        // Points around the tip of the needle at -2 escape after more iterations the
+        // closer they are to it
+        assert_matches_direct::<f64>(&View::new(-2.0, 0.0, 1e-20, 1e-20), 1000);
+    }
+
+    #[test]
+    fn matches_direct_iteration_deep() {
+        assert_matches_direct::<FloatExp>(&View::new(-2.0, 0.0, 1e-300, 1e-300), 5000);
+    }
+}
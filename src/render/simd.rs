@@ -0,0 +1,578 @@
+//! Escape time of several points at once
+//!
+//! The points are iterated in lanes of vectors of the instruction set of the CPU, AVX-512,
+//! AVX2 or NEON, which is selected at runtime. Two vectors are iterated side by side so that
+//! the latency of one is hidden by the other. With a grid of 64 × 64 points around a period 3
+//! minibrot and 10 000 iterations (`cargo bench -- simd`), AVX-512 is about five times and
+//! AVX2 about twice as fast as iterating the points one by one.
+//!
+//! Every lane does exactly the same floating point operations in the same order as iterating
+//! a single point directly, so the results are bit-identical.
+
+use num_complex::Complex64;
+
+use once_cell::sync::Lazy;
+
+use super::{in_main_components, BAILOUT, PERIODICITY_TOLERANCE};
+
+/// Iterates all points and stores the number of iterations and last value of every orbit
+type Kernel = fn(&[Complex64], Option<Complex64>, u32, bool, &mut [(u32, Complex64)]);
+
+/// Number of iterations between checking for finished lanes
+const STEPS_PER_REFILL: usize = 8;
+
+/// Kernel for the instruction set of the CPU, or `None` if it has no supported SIMD extension
+static KERNEL: Lazy<Option<Kernel>> = Lazy::new(|| {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            // SAFETY: AVX-512 is available
+            return Some(|points, julia, max_iterations, shortcut, results| unsafe {
+                iterate_avx512(points, julia, max_iterations, shortcut, results)
+            });
+        }
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is available
+            return Some(|points, julia, max_iterations, shortcut, results| unsafe {
+                iterate_avx2(points, julia, max_iterations, shortcut, results)
+            });
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: NEON is available
+            return Some(|points, julia, max_iterations, shortcut, results| unsafe {
+                iterate_neon(points, julia, max_iterations, shortcut, results)
+            });
+        }
+    }
+
+    None
+});
+
+/// Iterates all `points` like `EscapeTime::Direct` does without tracking anything
+///
+/// The points are starting values of the orbits of `julia` if given. With `shortcut`, points
+/// inside the main cardioid and period-2 bulb and orbits that become periodic stop early.
+/// Returns `None` if the CPU has no supported SIMD extension.
+pub fn iterate(
+    points: &[Complex64],
+    julia: Option<Complex64>,
+    max_iterations: u32,
+    shortcut: bool,
+) -> Option<Vec<(u32, Complex64)>> {
+    let kernel = (*KERNEL)?;
+
+    let mut results = vec![(0, Complex64::new(0.0, 0.0)); points.len()];
+    kernel(points, julia, max_iterations, shortcut, &mut results);
+
+    Some(results)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn iterate_avx512(
+    points: &[Complex64],
+    julia: Option<Complex64>,
+    max_iterations: u32,
+    shortcut: bool,
+    results: &mut [(u32, Complex64)],
+) {
+    iterate_lanes::<x86_64::__m512d, 16>(points, julia, max_iterations, shortcut, results);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn iterate_avx2(
+    points: &[Complex64],
+    julia: Option<Complex64>,
+    max_iterations: u32,
+    shortcut: bool,
+    results: &mut [(u32, Complex64)],
+) {
+    iterate_lanes::<x86_64::__m256d, 8>(points, julia, max_iterations, shortcut, results);
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn iterate_neon(
+    points: &[Complex64],
+    julia: Option<Complex64>,
+    max_iterations: u32,
+    shortcut: bool,
+    results: &mut [(u32, Complex64)],
+) {
+    iterate_lanes::<aarch64::float64x2_t, 4>(points, julia, max_iterations, shortcut, results);
+}
+
+/// Kernel of every instruction set of the CPU and lanes without SIMD instructions, by name
+///
+/// This is for comparing them in the tests and benchmarks.
+pub(super) fn kernels() -> Vec<(&'static str, Kernel)> {
+    // SAFETY: `f64` needs no instruction set
+    let mut kernels: Vec<(&'static str, Kernel)> = vec![
+        (
+            "1 lane",
+            |points, julia, max_iterations, shortcut, results| unsafe {
+                iterate_lanes::<f64, 1>(points, julia, max_iterations, shortcut, results)
+            },
+        ),
+        (
+            "4 lanes",
+            |points, julia, max_iterations, shortcut, results| unsafe {
+                iterate_lanes::<f64, 4>(points, julia, max_iterations, shortcut, results)
+            },
+        ),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            // SAFETY: AVX-512 is available
+            kernels.push((
+                "AVX-512",
+                |points, julia, max_iterations, shortcut, results| unsafe {
+                    iterate_avx512(points, julia, max_iterations, shortcut, results)
+                },
+            ));
+        }
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is available
+            kernels.push((
+                "AVX2",
+                |points, julia, max_iterations, shortcut, results| unsafe {
+                    iterate_avx2(points, julia, max_iterations, shortcut, results)
+                },
+            ));
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: NEON is available
+            kernels.push((
+                "NEON",
+                |points, julia, max_iterations, shortcut, results| unsafe {
+                    iterate_neon(points, julia, max_iterations, shortcut, results)
+                },
+            ));
+        }
+    }
+
+    kernels
+}
+
+/// Vector of `f64` lanes with the operations that iterating orbits needs
+///
+/// The operations are unsafe because the vector types need their instruction set, which only
+/// the functions above that are selected at runtime enable. Everything is inlined into them.
+trait Vector: Copy {
+    const LANES: usize;
+    /// Which lanes a comparison is true for
+    type Mask: Copy;
+
+    /// Loads the first `LANES` values of `values`
+    unsafe fn load(values: &[f64]) -> Self;
+    /// Stores the lanes in the first `LANES` values of `values`
+    unsafe fn store(self, values: &mut [f64]);
+    unsafe fn splat(value: f64) -> Self;
+    unsafe fn add(self, other: Self) -> Self;
+    unsafe fn sub(self, other: Self) -> Self;
+    unsafe fn mul(self, other: Self) -> Self;
+    unsafe fn lt(self, other: Self) -> Self::Mask;
+    unsafe fn eq(self, other: Self) -> Self::Mask;
+    unsafe fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    /// Lanes of `if_true` where `mask` is true and of `if_false` elsewhere
+    unsafe fn select(mask: Self::Mask, if_true: Self, if_false: Self) -> Self;
+}
+
+/// Single lanes without any SIMD instructions, for comparing the vectors against
+impl Vector for f64 {
+    const LANES: usize = 1;
+    type Mask = bool;
+
+    #[inline(always)]
+    unsafe fn load(values: &[f64]) -> Self {
+        values[0]
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, values: &mut [f64]) {
+        values[0] = self;
+    }
+
+    #[inline(always)]
+    unsafe fn splat(value: f64) -> Self {
+        value
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        self + other
+    }
+
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        self - other
+    }
+
+    #[inline(always)]
+    unsafe fn mul(self, other: Self) -> Self {
+        self * other
+    }
+
+    #[inline(always)]
+    unsafe fn lt(self, other: Self) -> bool {
+        self < other
+    }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> bool {
+        self == other
+    }
+
+    #[inline(always)]
+    unsafe fn and(a: bool, b: bool) -> bool {
+        a && b
+    }
+
+    #[inline(always)]
+    unsafe fn select(mask: bool, if_true: Self, if_false: Self) -> Self {
+        if mask {
+            if_true
+        } else {
+            if_false
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use std::arch::x86_64::*;
+    pub use std::arch::x86_64::{__m256d, __m512d};
+
+    use super::Vector;
+
+    /// Four lanes with AVX2
+    impl Vector for __m256d {
+        const LANES: usize = 4;
+        type Mask = __m256d;
+
+        #[inline(always)]
+        unsafe fn load(values: &[f64]) -> Self {
+            _mm256_loadu_pd(values[..4].as_ptr())
+        }
+
+        #[inline(always)]
+        unsafe fn store(self, values: &mut [f64]) {
+            _mm256_storeu_pd(values[..4].as_mut_ptr(), self)
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: f64) -> Self {
+            _mm256_set1_pd(value)
+        }
+
+        #[inline(always)]
+        unsafe fn add(self, other: Self) -> Self {
+            _mm256_add_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn sub(self, other: Self) -> Self {
+            _mm256_sub_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn mul(self, other: Self) -> Self {
+            _mm256_mul_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn lt(self, other: Self) -> Self {
+            _mm256_cmp_pd::<_CMP_LT_OQ>(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn eq(self, other: Self) -> Self {
+            _mm256_cmp_pd::<_CMP_EQ_OQ>(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn and(a: Self, b: Self) -> Self {
+            _mm256_and_pd(a, b)
+        }
+
+        #[inline(always)]
+        unsafe fn select(mask: Self, if_true: Self, if_false: Self) -> Self {
+            _mm256_blendv_pd(if_false, if_true, mask)
+        }
+    }
+
+    /// Eight lanes with AVX-512
+    impl Vector for __m512d {
+        const LANES: usize = 8;
+        type Mask = __mmask8;
+
+        #[inline(always)]
+        unsafe fn load(values: &[f64]) -> Self {
+            _mm512_loadu_pd(values[..8].as_ptr())
+        }
+
+        #[inline(always)]
+        unsafe fn store(self, values: &mut [f64]) {
+            _mm512_storeu_pd(values[..8].as_mut_ptr(), self)
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: f64) -> Self {
+            _mm512_set1_pd(value)
+        }
+
+        #[inline(always)]
+        unsafe fn add(self, other: Self) -> Self {
+            _mm512_add_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn sub(self, other: Self) -> Self {
+            _mm512_sub_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn mul(self, other: Self) -> Self {
+            _mm512_mul_pd(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn lt(self, other: Self) -> __mmask8 {
+            _mm512_cmp_pd_mask::<_CMP_LT_OQ>(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn eq(self, other: Self) -> __mmask8 {
+            _mm512_cmp_pd_mask::<_CMP_EQ_OQ>(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn and(a: __mmask8, b: __mmask8) -> __mmask8 {
+            a & b
+        }
+
+        #[inline(always)]
+        unsafe fn select(mask: __mmask8, if_true: Self, if_false: Self) -> Self {
+            _mm512_mask_blend_pd(mask, if_false, if_true)
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    pub use std::arch::aarch64::float64x2_t;
+    use std::arch::aarch64::*;
+
+    use super::Vector;
+
+    /// Two lanes with NEON
+    impl Vector for float64x2_t {
+        const LANES: usize = 2;
+        type Mask = uint64x2_t;
+
+        #[inline(always)]
+        unsafe fn load(values: &[f64]) -> Self {
+            vld1q_f64(values[..2].as_ptr())
+        }
+
+        #[inline(always)]
+        unsafe fn store(self, values: &mut [f64]) {
+            vst1q_f64(values[..2].as_mut_ptr(), self)
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: f64) -> Self {
+            vdupq_n_f64(value)
+        }
+
+        #[inline(always)]
+        unsafe fn add(self, other: Self) -> Self {
+            vaddq_f64(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn sub(self, other: Self) -> Self {
+            vsubq_f64(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn mul(self, other: Self) -> Self {
+            vmulq_f64(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn lt(self, other: Self) -> uint64x2_t {
+            vcltq_f64(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn eq(self, other: Self) -> uint64x2_t {
+            vceqq_f64(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn and(a: uint64x2_t, b: uint64x2_t) -> uint64x2_t {
+            vandq_u64(a, b)
+        }
+
+        #[inline(always)]
+        unsafe fn select(mask: uint64x2_t, if_true: Self, if_false: Self) -> Self {
+            vbslq_f64(mask, if_true, if_false)
+        }
+    }
+}
+
+/// Iterates `points` in `N` lanes, which are `N / V::LANES` vectors iterated side by side
+///
+/// This is inlined into the functions above so that it is compiled for their instruction set.
+/// Iteration counts are kept as `f64` so that all quantities of a lane are in vectors of the
+/// same width, they are exact far beyond `MAX_MAX_ITERATIONS`.
+#[inline(always)]
+unsafe fn iterate_lanes<V: Vector, const N: usize>(
+    points: &[Complex64],
+    julia: Option<Complex64>,
+    max_iterations: u32,
+    shortcut: bool,
+    results: &mut [(u32, Complex64)],
+) {
+    let max = max_iterations as f64;
+    // Without the shortcut no orbit is periodic and the checkpoint is never updated
+    let (tolerance, first_check) = if shortcut {
+        (PERIODICITY_TOLERANCE, 1.0)
+    } else {
+        (f64::NEG_INFINITY, f64::INFINITY)
+    };
+
+    // Index of the point in every lane, lanes without a point are at the iteration limit
+    let mut index = [None; N];
+    let mut next = 0;
+
+    let (mut cr, mut ci) = ([0.0; N], [0.0; N]);
+    let (mut zr, mut zi) = ([0.0; N], [0.0; N]);
+    let mut it = [max; N];
+    // Brent's cycle detection like for single points
+    let (mut check_r, mut check_i) = ([0.0; N], [0.0; N]);
+    let mut next_check = [first_check; N];
+
+    loop {
+        // Store the results of finished lanes and continue with the next point in them, so
+        // that lanes don't have to wait for the slowest one
+        for lane in 0..N {
+            if zr[lane] * zr[lane] + zi[lane] * zi[lane] < BAILOUT && it[lane] < max {
+                continue;
+            }
+
+            if let Some(index) = index[lane].take() {
+                results[index] = (it[lane] as u32, Complex64::new(zr[lane], zi[lane]));
+            }
+
+            while let Some(&point) = points.get(next) {
+                next += 1;
+
+                let (z, c) = match julia {
+                    Some(julia) => (point, julia),
+                    None if shortcut && in_main_components(point) => {
+                        results[next - 1] = (max_iterations, Complex64::new(0.0, 0.0));
+                        continue;
+                    }
+                    None => (Complex64::new(0.0, 0.0), point),
+                };
+
+                index[lane] = Some(next - 1);
+                (cr[lane], ci[lane]) = (c.re, c.im);
+                (zr[lane], zi[lane]) = (z.re, z.im);
+                it[lane] = 0.0;
+                (check_r[lane], check_i[lane]) = (z.re, z.im);
+                next_check[lane] = first_check;
+                break;
+            }
+        }
+
+        if index.iter().all(Option::is_none) {
+            break;
+        }
+
+        let (bailout, max, tolerance) = (V::splat(BAILOUT), V::splat(max), V::splat(tolerance));
+        let one = V::splat(1.0);
+        for _ in 0..STEPS_PER_REFILL {
+            for lanes in (0..N).step_by(V::LANES) {
+                let lanes = lanes..lanes + V::LANES;
+                let (mut zr_v, mut zi_v) =
+                    (V::load(&zr[lanes.clone()]), V::load(&zi[lanes.clone()]));
+                let (cr_v, ci_v) = (V::load(&cr[lanes.clone()]), V::load(&ci[lanes.clone()]));
+                let mut it_v = V::load(&it[lanes.clone()]);
+                let (mut check_r_v, mut check_i_v) = (
+                    V::load(&check_r[lanes.clone()]),
+                    V::load(&check_i[lanes.clone()]),
+                );
+                let mut next_check_v = V::load(&next_check[lanes.clone()]);
+
+                let magnitude = zr_v.mul(zr_v).add(zi_v.mul(zi_v));
+                let active = V::and(magnitude.lt(bailout), it_v.lt(max));
+
+                // Same operations as `z * z + c` with `Complex64`
+                let r = zr_v.mul(zr_v).sub(zi_v.mul(zi_v)).add(cr_v);
+                let i = zr_v.mul(zi_v).add(zi_v.mul(zr_v)).add(ci_v);
+                zr_v = V::select(active, r, zr_v);
+                zi_v = V::select(active, i, zi_v);
+                it_v = V::select(active, it_v.add(one), it_v);
+
+                let (dr, di) = (zr_v.sub(check_r_v), zi_v.sub(check_i_v));
+                let periodic = V::and(active, dr.mul(dr).add(di.mul(di)).lt(tolerance));
+                let update = V::and(active, it_v.eq(next_check_v));
+                it_v = V::select(periodic, max, it_v);
+                check_r_v = V::select(update, zr_v, check_r_v);
+                check_i_v = V::select(update, zi_v, check_i_v);
+                next_check_v = V::select(update, next_check_v.add(next_check_v), next_check_v);
+
+                zr_v.store(&mut zr[lanes.clone()]);
+                zi_v.store(&mut zi[lanes.clone()]);
+                it_v.store(&mut it[lanes.clone()]);
+                check_r_v.store(&mut check_r[lanes.clone()]);
+                check_i_v.store(&mut check_i[lanes.clone()]);
+                next_check_v.store(&mut next_check[lanes]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::support::{boundary_views, Grid};
+
+    const MAX_ITERATIONS: u32 = 10_000;
+
+    /// Iterating points in lanes gives bit-identical results to iterating them one by one,
+    /// for views with points that escape slowly and orbits that become periodic
+    #[test]
+    fn lanes_match_single_points() {
+        const SIZE: usize = 32;
+
+        for (_, view, julia) in boundary_views() {
+            for shortcut in [true, false] {
+                let grid = Grid::new(&view, julia, SIZE, shortcut);
+                let expected = grid.iterate(MAX_ITERATIONS);
+
+                for name in grid.lane_kernels() {
+                    let results = grid.iterate_lanes(name, MAX_ITERATIONS);
+                    for (&(it, z), &(expected_it, expected_z)) in results.iter().zip(&expected) {
+                        assert_eq!(it, expected_it, "{} {:?} {:?}", name, view, julia);
+                        assert_eq!(z.re.to_bits(), expected_z.re.to_bits());
+                        assert_eq!(z.im.to_bits(), expected_z.im.to_bits());
+                    }
+                }
+            }
+        }
+    }
+}
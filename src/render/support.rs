@@ -0,0 +1,135 @@
+//! Views and grids of points shared by the tests and the benchmarks
+//!
+//! The benchmarks only see the public interface of the crate, so this also wraps the ways of
+//! iterating points that they compare.
+
+use num_complex::Complex64;
+
+use super::floatexp::FloatExp;
+use super::orbit::TrackingOptions;
+use super::{simd, EscapeTime, Fractal, View};
+
+/// Positions of a grid of `size` × `size` points covering the whole view
+pub fn grid(size: usize) -> Vec<Complex64> {
+    (0..size * size)
+        .map(|i| {
+            Complex64::new(
+                (i % size) as f64 / (size - 1) as f64 - 0.5,
+                (i / size) as f64 / (size - 1) as f64 - 0.5,
+            )
+        })
+        .collect()
+}
+
+/// Views with many points close to the boundary, some of which escape only after many
+/// iterations, and the Julia constant to render instead of the Mandelbrot set, if any
+pub fn boundary_views() -> Vec<(&'static str, View, Option<Complex64>)> {
+    vec![
+        // Cusp of the main cardioid
+        ("cusp", View::new(0.2501, 0.0, 2e-4, 2e-4), None),
+        // Between the main cardioid and the period 2 bulb
+        ("neck", View::new(-0.75, 0.0, 1e-2, 1e-2), None),
+        // Period 3 minibrot on the needle
+        ("minibrot", View::new(-1.7549, 0.0, 2e-2, 2e-2), None),
+        ("seahorse", View::new(-0.7436, 0.1318, 2e-3, 2e-3), None),
+        // Julia sets of points close to where the period 3 bulb is attached
+        (
+            "julia",
+            View::new(0.0, 0.0, 3.0, 3.0),
+            Some(Complex64::new(-0.1225, 0.66)),
+        ),
+        (
+            "julia 2",
+            View::new(0.0, 0.0, 3.0, 3.0),
+            Some(Complex64::new(-0.124, 0.651)),
+        ),
+    ]
+}
+
+/// Grid of points of a view of the Mandelbrot set or one of its Julia sets, iterated directly
+/// with `f64` and without tracking anything
+pub struct Grid {
+    escape_time: EscapeTime,
+    positions: Vec<Complex64>,
+    /// Starting values or constants of the orbits, for iterating them in SIMD lanes
+    pub(super) points: Vec<Complex64>,
+    pub(super) julia: Option<Complex64>,
+    periodicity_check: bool,
+}
+
+impl Grid {
+    /// `size` × `size` points covering `view`, whose orbits stop early once they become
+    /// periodic with `periodicity_check`
+    pub fn new(
+        view: &View,
+        julia: Option<Complex64>,
+        size: usize,
+        periodicity_check: bool,
+    ) -> Self {
+        let pixel_size = view.width() * FloatExp::from_f64(1.0 / (size - 1) as f64);
+        let options = TrackingOptions {
+            interior: !periodicity_check,
+            ..TrackingOptions::default()
+        };
+        let escape_time = EscapeTime::direct(view, pixel_size, options, Fractal::Mandelbrot, julia);
+        let positions = grid(size);
+        let points = positions
+            .iter()
+            .map(|&position| escape_time.point(position))
+            .collect();
+
+        Grid {
+            escape_time,
+            positions,
+            points,
+            julia,
+            periodicity_check,
+        }
+    }
+
+    /// Number of iterations and last value of the orbit of every point, iterated one by one
+    pub fn iterate(&self, max_iterations: u32) -> Vec<(u32, Complex64)> {
+        self.positions
+            .iter()
+            .map(|&position| {
+                let (iterations, z, _) = self.escape_time.iterate(position, max_iterations);
+                (iterations, z)
+            })
+            .collect()
+    }
+
+    /// Like `iterate`, but in lanes of the best SIMD extension of the CPU, or `None` if it
+    /// has no supported one
+    pub fn iterate_simd(&self, max_iterations: u32) -> Option<Vec<(u32, Complex64)>> {
+        simd::iterate(
+            &self.points,
+            self.julia,
+            max_iterations,
+            self.periodicity_check,
+        )
+    }
+
+    /// Names of the ways of iterating points in lanes that the CPU supports, with and without
+    /// SIMD instructions
+    pub fn lane_kernels(&self) -> Vec<&'static str> {
+        simd::kernels().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Like `iterate`, but in lanes with the kernel called `name`
+    pub fn iterate_lanes(&self, name: &str, max_iterations: u32) -> Vec<(u32, Complex64)> {
+        let (_, kernel) = simd::kernels()
+            .into_iter()
+            .find(|(kernel, _)| *kernel == name)
+            .expect("kernel is supported");
+        let mut results = vec![(0, Complex64::new(0.0, 0.0)); self.points.len()];
+        kernel(
+            &self.points,
+            self.julia,
+            max_iterations,
+            self.periodicity_check,
+            &mut results,
+        );
+
+        results
+    }
+}
@@ -1,51 +1,15 @@
 use gtk::{gdk, glib, graphene, gsk, prelude::*, subclass::prelude::*};
 
-use num_complex::Complex64;
-
-use rayon::prelude::*;
-
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::sync::mpsc;
 
-use once_cell::sync::Lazy;
-
-#[cfg(target_endian = "big")]
-#[repr(C, packed)]
-#[derive(Clone, Copy, Debug, zerocopy::IntoBytes, zerocopy::Immutable)]
-struct Pixel {
-    b: u8,
-    g: u8,
-    r: u8,
-    a: u8,
-}
-#[cfg(target_endian = "little")]
-#[repr(C, packed)]
-#[derive(Clone, Copy, Debug, zerocopy::IntoBytes, zerocopy::Immutable)]
-struct Pixel {
-    #[allow(dead_code)]
-    a: u8,
-    r: u8,
-    g: u8,
-    b: u8,
-}
-
-impl Default for Pixel {
-    fn default() -> Self {
-        Pixel {
-            a: 255,
-            r: 0,
-            g: 0,
-            b: 0,
-        }
-    }
-}
-
-#[derive(Debug)]
-struct Image {
-    pixels: Vec<Pixel>,
-    width: usize,
-    height: usize,
-}
+use crate::render::{
+    self, CancellationToken, ColorMapping, Coloring, ColoringAlgorithm, Command,
+    DistanceEstimation, Formula, Fractal, FractalFamily, InteriorColoring, Interpolation,
+    IterationMode, Palette, Polynomial, RenderMode, Tile, View, DEFAULT_MAX_ITERATIONS,
+    MAX_MAX_ITERATIONS,
+};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 struct Rectangle {
@@ -55,26 +19,134 @@ struct Rectangle {
     height: f64,
 }
 
+/// Rendered tile that is ready for drawing
 #[derive(Debug)]
-enum Command {
-    Render {
-        rect: Rectangle,
-        target_width: usize,
-        target_height: usize,
-    },
-    Quit,
+struct TileTexture {
+    texture: gdk::MemoryTexture,
+    /// Position and size in surface coordinates
+    rect: graphene::Rect,
+    frame: u64,
+    scale: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, glib::Properties)]
+#[properties(wrapper_type = super::Widget)]
 pub struct Widget {
-    view: Cell<Rectangle>,
+    /// Maximum number of iterations before a point is considered to be part of the set
+    #[property(get, set = Self::set_max_iterations, minimum = 1, maximum = MAX_MAX_ITERATIONS, default = DEFAULT_MAX_ITERATIONS)]
+    max_iterations: Cell<u32>,
+    /// How the iteration limit used for rendering is derived from `max-iterations`
+    #[property(get, set = Self::set_iteration_mode, builder(IterationMode::Fixed))]
+    iteration_mode: Cell<IterationMode>,
+    /// Whether uniform rectangles are filled without iterating their inside
+    #[property(get, set = Self::set_subdivision)]
+    subdivision: Cell<bool>,
+    /// Whether escape times or the density of escaping orbits are shown
+    #[property(get, set = Self::set_render_mode, builder(RenderMode::EscapeTime))]
+    render_mode: Cell<RenderMode>,
+    /// Fractal whose set of all points `c` or Julia set is shown
+    #[property(get, set = Self::set_fractal, builder(FractalFamily::Mandelbrot))]
+    fractal: Cell<FractalFamily>,
+    /// Exponent of the Multibrot fractal
+    #[property(get, set = Self::set_exponent, minimum = 1.1, maximum = 16.0, default = 3.0)]
+    exponent: Cell<f64>,
+    /// Weight of the previous value of the orbit in the Phoenix fractal
+    #[property(get, set = Self::set_phoenix_p, minimum = -2.0, maximum = 2.0, default = -0.5)]
+    phoenix_p: Cell<f64>,
+    /// Iteration and escape condition of the Formula fractal
+    #[property(get, set = Self::set_formula)]
+    formula: RefCell<Formula>,
+    /// Polynomial whose roots the Newton fractal shows the basins of
+    #[property(get, set = Self::set_polynomial)]
+    polynomial: RefCell<Polynomial>,
+    /// Whether the Julia set of `julia-x` + `julia-y`i is shown instead of the set of all `c`
+    #[property(get, set = Self::set_julia)]
+    julia: Cell<bool>,
+    /// Real part of the constant of the Julia set
+    #[property(get, set = Self::set_julia_x, default = -0.8)]
+    julia_x: Cell<f64>,
+    /// Imaginary part of the constant of the Julia set
+    #[property(get, set = Self::set_julia_y, default = 0.156)]
+    julia_y: Cell<f64>,
+    /// Real part of the point that was last picked by clicking or by moving the pointer while
+    /// holding Ctrl
+    #[property(get)]
+    picked_x: Cell<f64>,
+    /// Imaginary part of the point that was last picked
+    #[property(get)]
+    picked_y: Cell<f64>,
+    /// Iteration limit that was used for the currently displayed image
+    #[property(get)]
+    effective_max_iterations: Cell<u32>,
+    /// Palette that escaped points are colored with
+    #[property(get, set = Self::set_palette)]
+    palette: RefCell<Palette>,
+    /// Number of iterations after which the palette repeats
+    #[property(get, set = Self::set_palette_period, minimum = 1.0, maximum = 100_000.0, default = 360.0)]
+    palette_period: Cell<f64>,
+    /// Offset into the color palette, as fraction of its period
+    #[property(get, set = Self::set_color_offset, minimum = 0.0, maximum = 1.0, default = 0.0)]
+    color_offset: Cell<f64>,
+    /// Whether the palette is traversed in reverse
+    #[property(get, set = Self::set_palette_reversed)]
+    palette_reversed: Cell<bool>,
+    /// Color space in which colors between the palette stops are blended
+    #[property(get, set = Self::set_interpolation, builder(Interpolation::Srgb))]
+    interpolation: Cell<Interpolation>,
+    /// How iteration counts are mapped to positions in the palette
+    #[property(get, set = Self::set_color_mapping, builder(ColorMapping::Smooth))]
+    color_mapping: Cell<ColorMapping>,
+    /// Weight of the histogram-equalized palette position against the smooth one
+    #[property(get, set = Self::set_histogram_blend, minimum = 0.0, maximum = 1.0, default = 1.0)]
+    histogram_blend: Cell<f64>,
+    /// How the estimated distance to the boundary of the set is used, if at all
+    #[property(get, set = Self::set_distance_estimation, builder(DistanceEstimation::Off))]
+    distance_estimation: Cell<DistanceEstimation>,
+    /// Distance to the boundary in rendered pixels below which points are part of it
+    #[property(get, set = Self::set_boundary_width, minimum = 0.1, maximum = 100.0, default = 1.0)]
+    boundary_width: Cell<f64>,
+    /// Whether the image is shaded as if it was a surface lit from the side
+    #[property(get, set = Self::set_lighting)]
+    lighting: Cell<bool>,
+    /// Direction the light comes from in degrees, counter-clockwise from the right
+    #[property(get, set = Self::set_light_angle, minimum = 0.0, maximum = 360.0, default = 45.0)]
+    light_angle: Cell<f64>,
+    /// Height of the light above the surface, relative to its distance
+    #[property(get, set = Self::set_light_height, minimum = 0.0, maximum = 10.0, default = 1.5)]
+    light_height: Cell<f64>,
+    /// Algorithm that determines the palette position of escaped points
+    #[property(get, set = Self::set_coloring_algorithm, builder(ColoringAlgorithm::EscapeTime))]
+    coloring_algorithm: Cell<ColoringAlgorithm>,
+    /// Number of stripes per turn for the stripe average
+    #[property(get, set = Self::set_stripe_density, minimum = 1.0, maximum = 100.0, default = 5.0)]
+    stripe_density: Cell<f64>,
+    /// Real part of the center of the orbit traps
+    #[property(get, set = Self::set_trap_x, minimum = -4.0, maximum = 4.0, default = 0.0)]
+    trap_x: Cell<f64>,
+    /// Imaginary part of the center of the orbit traps
+    #[property(get, set = Self::set_trap_y, minimum = -4.0, maximum = 4.0, default = 0.0)]
+    trap_y: Cell<f64>,
+    /// Angle of the line and cross traps in degrees
+    #[property(get, set = Self::set_trap_angle, minimum = 0.0, maximum = 180.0, default = 0.0)]
+    trap_angle: Cell<f64>,
+    /// How points inside the set are colored
+    #[property(get, set = Self::set_interior_coloring, builder(InteriorColoring::Solid))]
+    interior_coloring: Cell<InteriorColoring>,
+    view: RefCell<View>,
     surface_size: Cell<(usize, usize)>,
-    texture: RefCell<Option<gdk::MemoryTexture>>,
+    /// Most recent tile for every tile position
+    tiles: RefCell<HashMap<(usize, usize), TileTexture>>,
+    /// Number of the last frame that was requested from the render thread
+    frame: Cell<u64>,
+    /// Tiles of frames before this one are outdated and not shown anymore
+    first_frame: Cell<u64>,
     zoom_controller: gtk::GestureDrag,
     zoom_controller_cancelled: Cell<bool>,
     move_controller: gtk::GestureDrag,
     command_sender: mpsc::Sender<Command>,
-    surface_receiver: RefCell<Option<async_channel::Receiver<Image>>>,
+    /// Cancels the render that is currently in progress
+    render_cancellation: RefCell<CancellationToken>,
+    tile_receiver: RefCell<Option<async_channel::Receiver<Tile>>>,
     channel_source: RefCell<Option<glib::Source>>,
 }
 
@@ -83,18 +155,13 @@ impl Default for Widget {
         use std::thread;
 
         let (command_sender, command_receiver) = mpsc::channel();
-        let (surface_sender, surface_receiver) = async_channel::bounded(1);
+        let (tile_sender, tile_receiver) = async_channel::unbounded();
 
         thread::spawn(move || {
-            render_thread(&command_receiver, &surface_sender);
+            render::render_thread(&command_receiver, &tile_sender);
         });
 
-        let view = Rectangle {
-            x: -0.75,
-            y: 0.0,
-            width: 0.0,
-            height: 0.0,
-        };
+        let view = Fractal::Mandelbrot.initial_view(false);
 
         let zoom_controller = gtk::GestureDrag::new();
         zoom_controller.set_button(1);
@@ -102,14 +169,50 @@ impl Default for Widget {
         move_controller.set_button(3);
 
         Widget {
-            view: Cell::new(view),
+            max_iterations: Cell::new(DEFAULT_MAX_ITERATIONS),
+            iteration_mode: Cell::new(IterationMode::Fixed),
+            subdivision: Cell::new(false),
+            render_mode: Cell::new(RenderMode::EscapeTime),
+            fractal: Cell::new(FractalFamily::Mandelbrot),
+            exponent: Cell::new(3.0),
+            phoenix_p: Cell::new(-0.5),
+            formula: RefCell::new(Formula::default()),
+            polynomial: RefCell::new(Polynomial::default()),
+            julia: Cell::new(false),
+            julia_x: Cell::new(-0.8),
+            julia_y: Cell::new(0.156),
+            picked_x: Cell::new(0.0),
+            picked_y: Cell::new(0.0),
+            effective_max_iterations: Cell::new(DEFAULT_MAX_ITERATIONS),
+            palette: RefCell::new(Palette::default()),
+            palette_period: Cell::new(360.0),
+            color_offset: Cell::new(0.0),
+            palette_reversed: Cell::new(false),
+            interpolation: Cell::new(Interpolation::Srgb),
+            color_mapping: Cell::new(ColorMapping::Smooth),
+            histogram_blend: Cell::new(1.0),
+            distance_estimation: Cell::new(DistanceEstimation::Off),
+            boundary_width: Cell::new(1.0),
+            lighting: Cell::new(false),
+            light_angle: Cell::new(45.0),
+            light_height: Cell::new(1.5),
+            coloring_algorithm: Cell::new(ColoringAlgorithm::EscapeTime),
+            stripe_density: Cell::new(5.0),
+            trap_x: Cell::new(0.0),
+            trap_y: Cell::new(0.0),
+            trap_angle: Cell::new(0.0),
+            interior_coloring: Cell::new(InteriorColoring::Solid),
+            view: RefCell::new(view),
             surface_size: Cell::new((0, 0)),
-            texture: RefCell::new(None),
+            tiles: RefCell::new(HashMap::new()),
+            frame: Cell::new(0),
+            first_frame: Cell::new(0),
             zoom_controller,
             zoom_controller_cancelled: Cell::new(false),
             move_controller,
             command_sender,
-            surface_receiver: RefCell::new(Some(surface_receiver)),
+            render_cancellation: RefCell::new(CancellationToken::default()),
+            tile_receiver: RefCell::new(Some(tile_receiver)),
             channel_source: RefCell::new(None),
         }
     }
@@ -117,6 +220,7 @@ impl Default for Widget {
 
 impl Drop for Widget {
     fn drop(&mut self) {
+        self.render_cancellation.borrow().cancel();
         let _ = self.command_sender.send(Command::Quit);
         if let Some(source) = self.channel_source.borrow_mut().take() {
             source.destroy();
@@ -131,6 +235,7 @@ impl ObjectSubclass for Widget {
     type ParentType = gtk::Widget;
 }
 
+#[glib::derived_properties]
 impl ObjectImpl for Widget {
     fn constructed(&self) {
         self.parent_constructed();
@@ -220,6 +325,25 @@ impl ObjectImpl for Widget {
 
         obj.add_controller(self.move_controller.clone());
 
+        let motion_controller = gtk::EventControllerMotion::new();
+
+        motion_controller.connect_motion(move |controller, x, y| {
+            if controller
+                .current_event_state()
+                .contains(gdk::ModifierType::CONTROL_MASK)
+            {
+                let widget = controller
+                    .widget()
+                    .unwrap()
+                    .downcast::<super::Widget>()
+                    .unwrap();
+                let imp = widget.imp();
+                imp.pick(x, y);
+            }
+        });
+
+        obj.add_controller(motion_controller);
+
         let key_controller = gtk::EventControllerKey::new();
 
         key_controller.connect_key_pressed(move |controller, keyval, keycode, state| {
@@ -237,13 +361,13 @@ impl ObjectImpl for Widget {
 
         let imp_weak = self.downgrade();
         let main_context = glib::MainContext::default();
-        let surface_receiver = self.surface_receiver.borrow_mut().take().unwrap();
+        let tile_receiver = self.tile_receiver.borrow_mut().take().unwrap();
         let join_handle = main_context.spawn_local(async move {
-            while let Ok(image) = surface_receiver.recv().await {
+            while let Ok(tile) = tile_receiver.recv().await {
                 let Some(imp) = imp_weak.upgrade() else {
                     break;
                 };
-                imp.on_render_done(image);
+                imp.on_render_done(tile);
             }
         });
 
@@ -252,6 +376,18 @@ impl ObjectImpl for Widget {
 }
 
 impl WidgetImpl for Widget {
+    /// Renders the current view again because nothing was rendered while unmapped
+    fn map(&self) {
+        self.parent_map();
+        self.rerender();
+    }
+
+    /// Stops rendering while the widget is not shown, e.g. the hidden Julia set
+    fn unmap(&self) {
+        self.render_cancellation.borrow().cancel();
+        self.parent_unmap();
+    }
+
     fn size_allocate(&self, width: i32, height: i32, _baseline: i32) {
         self.on_resize(width, height);
     }
@@ -266,26 +402,17 @@ impl Widget {
         let old_size = self.surface_size.get();
         let new_size = (width as usize, height as usize);
         if new_size != old_size && new_size.0 != 0 && new_size.1 != 0 {
-            let mut view = self.view.get();
-            if old_size.0 != 0 && old_size.1 != 0 {
-                view.width *= new_size.0 as f64 / old_size.0 as f64;
-                view.height *= new_size.1 as f64 / old_size.1 as f64;
-            } else {
-                const MIN_WIDTH: f64 = 3.5;
-                const MIN_HEIGHT: f64 = 2.0;
-                if new_size.0 as f64 / new_size.1 as f64 * MIN_HEIGHT < MIN_WIDTH {
-                    // Constrained by width, derive height from aspect ratio
-                    view.width = MIN_WIDTH;
-                    view.height = new_size.1 as f64 / new_size.0 as f64 * MIN_WIDTH;
+            {
+                let mut view = self.view.borrow_mut();
+                if old_size.0 != 0 && old_size.1 != 0 {
+                    view.scale(
+                        new_size.0 as f64 / old_size.0 as f64,
+                        new_size.1 as f64 / old_size.1 as f64,
+                    );
                 } else {
-                    // Width is fine, derive it from aspect ratio
-                    view.height = MIN_HEIGHT;
-                    view.width = new_size.0 as f64 / new_size.1 as f64 * MIN_HEIGHT;
+                    *view = self.initial_view(new_size);
                 }
-                view.x -= view.width / 2.0;
-                view.y -= view.height / 2.0;
             }
-            self.view.set(view);
 
             self.surface_size.set(new_size);
             self.obj().queue_draw();
@@ -293,6 +420,37 @@ impl Widget {
         }
     }
 
+    /// View that contains the whole set on a surface of `size`
+    fn initial_view(&self, size: (usize, usize)) -> View {
+        let mut view = self.fractal().initial_view(self.julia.get());
+        let (min_width, min_height) = (view.width().to_f64(), view.height().to_f64());
+
+        if size.0 as f64 / size.1 as f64 * min_height < min_width {
+            // Constrained by width, derive height from aspect ratio
+            view.set_size(min_width, size.1 as f64 / size.0 as f64 * min_width);
+        } else {
+            // Width is fine, derive it from aspect ratio
+            view.set_size(size.0 as f64 / size.1 as f64 * min_height, min_height);
+        }
+
+        view
+    }
+
+    /// Shows the whole set again after switching to a different one
+    fn reset_view(&self) {
+        let surface_size = self.surface_size.get();
+        if surface_size.0 == 0 || surface_size.1 == 0 {
+            return;
+        }
+
+        *self.view.borrow_mut() = self.initial_view(surface_size);
+
+        self.tiles.borrow_mut().clear();
+        self.trigger_render();
+        self.first_frame.set(self.frame.get());
+        self.obj().queue_draw();
+    }
+
     fn on_snapshot(&self, snapshot: &gtk::Snapshot) {
         let surface_size = self.surface_size.get();
 
@@ -301,17 +459,18 @@ impl Widget {
             &graphene::Rect::new(0.0, 0.0, surface_size.0 as f32, surface_size.1 as f32),
         );
 
-        if let Some(ref texture) = *self.texture.borrow() {
-            snapshot.append_texture(
-                texture,
-                &graphene::Rect::new(
-                    0.0,
-                    0.0,
-                    texture.width() as f32 / 2.0,
-                    texture.height() as f32 / 2.0,
-                ),
-            );
+        // The size of coarse tiles at the edges is rounded up, and tiles of previous frames
+        // might be outside the surface after resizing
+        snapshot.push_clip(&graphene::Rect::new(
+            0.0,
+            0.0,
+            surface_size.0 as f32,
+            surface_size.1 as f32,
+        ));
+        for tile in self.tiles.borrow().values() {
+            snapshot.append_texture(&tile.texture, &tile.rect);
         }
+        snapshot.pop();
 
         if self.zoom_controller.is_recognized() {
             if let (Some((x, y)), Some((width, height))) = (
@@ -379,6 +538,13 @@ impl Widget {
 
             let rect = calculate_selection_rectangle(rect, self.surface_size.get());
 
+            // Clicking without dragging picks a point instead of zooming into nothing
+            if rect.width.abs() < 1.0 || rect.height.abs() < 1.0 {
+                self.pick(x, y);
+                self.obj().queue_draw();
+                return;
+            }
+
             let (x1, x2, y1, y2) = (
                 f64::min(rect.x, rect.x + rect.width),
                 f64::max(rect.x, rect.x + rect.width),
@@ -386,22 +552,22 @@ impl Widget {
                 f64::max(rect.y, rect.y + rect.height),
             );
 
-            let view = self.view.get();
-            let surface_size = self.surface_size.get();
-            let (xscale, yscale) = (
-                view.width / surface_size.0 as f64,
-                view.height / surface_size.1 as f64,
-            );
+            {
+                let mut view = self.view.borrow_mut();
+                let surface_size = self.surface_size.get();
+                let (width, height) = (surface_size.0 as f64, surface_size.1 as f64);
 
-            self.view.set(Rectangle {
-                x: view.x + x1 * xscale,
-                y: view.y + y1 * yscale,
-                width: (x2 - x1) * xscale,
-                height: (y2 - y1) * yscale,
-            });
+                view.zoom(
+                    x1 / width,
+                    y1 / height,
+                    (x2 - x1) / width,
+                    (y2 - y1) / height,
+                );
+            }
 
-            let _ = self.texture.borrow_mut().take();
+            self.tiles.borrow_mut().clear();
             self.trigger_render();
+            self.first_frame.set(self.frame.get());
         }
 
         self.obj().queue_draw();
@@ -422,116 +588,511 @@ impl Widget {
 
     fn on_move_end(&self, _controller: &gtk::GestureDrag, _off_x: f64, _off_y: f64) {
         if let Some((x, y)) = self.move_controller.offset() {
-            let mut view = self.view.get();
-            let surface_size = self.surface_size.get();
-            view.x -= x / surface_size.0 as f64 * view.width;
-            view.y -= y / surface_size.1 as f64 * view.height;
-            self.view.set(view);
+            let (dx, dy) = self.move_translation(x, y);
+            self.view.borrow_mut().translate(dx, dy);
 
             self.obj().queue_draw();
             self.trigger_render();
         }
     }
 
+    /// Picks the point at `x`, `y` in surface coordinates
+    fn pick(&self, x: f64, y: f64) {
+        let surface_size = self.surface_size.get();
+        if surface_size.0 == 0 || surface_size.1 == 0 {
+            return;
+        }
+
+        let (picked_x, picked_y) = self
+            .view
+            .borrow()
+            .point(x / surface_size.0 as f64, y / surface_size.1 as f64);
+
+        let obj = self.obj();
+        let _guard = obj.freeze_notify();
+        if self.picked_x.replace(picked_x) != picked_x {
+            obj.notify_picked_x();
+        }
+        if self.picked_y.replace(picked_y) != picked_y {
+            obj.notify_picked_y();
+        }
+    }
+
     fn on_key_pressed(&self, keyval: gdk::Key, _keycode: u32, _state: gdk::ModifierType) {
-        if keyval == gdk::Key::Escape {
-            self.zoom_controller.reset();
-            self.obj().queue_draw();
+        match keyval {
+            gdk::Key::Escape => {
+                self.zoom_controller.reset();
+                self.obj().queue_draw();
+            }
+            gdk::Key::plus | gdk::Key::equal | gdk::Key::KP_Add => {
+                let max_iterations = self.max_iterations.get().saturating_mul(2);
+                self.obj()
+                    .set_max_iterations(u32::min(max_iterations, MAX_MAX_ITERATIONS));
+            }
+            gdk::Key::minus | gdk::Key::KP_Subtract => {
+                let max_iterations = self.max_iterations.get() / 2;
+                self.obj().set_max_iterations(u32::max(max_iterations, 1));
+            }
+            _ => (),
         }
     }
 
-    fn on_render_done(&self, image: Image) {
-        let (width, height, stride) = (image.width as i32, image.height as i32, image.width * 4);
-        let texture = gdk::MemoryTexture::new(
-            width,
-            height,
-            gdk::MemoryFormat::A8r8g8b8,
-            &glib::Bytes::from_owned(image),
-            stride,
-        );
+    fn set_max_iterations(&self, max_iterations: u32) {
+        if self.max_iterations.replace(max_iterations) == max_iterations {
+            return;
+        }
 
-        *self.texture.borrow_mut() = Some(texture);
-        self.obj().queue_draw();
+        self.rerender();
     }
 
-    fn trigger_render(&self) {
-        let mut rect = self.view.get();
+    fn set_iteration_mode(&self, iteration_mode: IterationMode) {
+        if self.iteration_mode.replace(iteration_mode) == iteration_mode {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_subdivision(&self, subdivision: bool) {
+        if self.subdivision.replace(subdivision) == subdivision {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_render_mode(&self, render_mode: RenderMode) {
+        if self.render_mode.replace(render_mode) == render_mode {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_fractal(&self, fractal: FractalFamily) {
+        if self.fractal.replace(fractal) == fractal {
+            return;
+        }
+
+        self.reset_view();
+    }
+
+    /// The view is reset because the size of the set depends on the exponent
+    fn set_exponent(&self, exponent: f64) {
+        if self.exponent.replace(exponent) == exponent
+            || self.fractal.get() != FractalFamily::Multibrot
+        {
+            return;
+        }
+
+        self.reset_view();
+    }
+
+    fn set_phoenix_p(&self, phoenix_p: f64) {
+        if self.phoenix_p.replace(phoenix_p) == phoenix_p
+            || self.fractal.get() != FractalFamily::Phoenix
+        {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_formula(&self, formula: Formula) {
+        if self.formula.replace(formula.clone()) == formula
+            || self.fractal.get() != FractalFamily::Formula
+        {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    /// The view is reset to show all roots of the new polynomial
+    fn set_polynomial(&self, polynomial: Polynomial) {
+        if self.polynomial.replace(polynomial.clone()) == polynomial
+            || self.fractal.get() != FractalFamily::Newton
+        {
+            return;
+        }
+
+        self.reset_view();
+    }
+
+    fn set_julia(&self, julia: bool) {
+        if self.julia.replace(julia) == julia {
+            return;
+        }
+
+        self.reset_view();
+    }
+
+    fn set_julia_x(&self, julia_x: f64) {
+        if self.julia_x.replace(julia_x) == julia_x || !self.julia.get() {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_julia_y(&self, julia_y: f64) {
+        if self.julia_y.replace(julia_y) == julia_y || !self.julia.get() {
+            return;
+        }
+
+        self.rerender();
+    }
+
+    fn set_palette(&self, palette: Palette) {
+        if self.palette.replace(palette.clone()) == palette {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_palette_period(&self, palette_period: f64) {
+        if self.palette_period.replace(palette_period) == palette_period {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_palette_reversed(&self, palette_reversed: bool) {
+        if self.palette_reversed.replace(palette_reversed) == palette_reversed {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_color_offset(&self, color_offset: f64) {
+        if self.color_offset.replace(color_offset) == color_offset {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_interpolation(&self, interpolation: Interpolation) {
+        if self.interpolation.replace(interpolation) == interpolation {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_color_mapping(&self, color_mapping: ColorMapping) {
+        if self.color_mapping.replace(color_mapping) == color_mapping {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_histogram_blend(&self, histogram_blend: f64) {
+        if self.histogram_blend.replace(histogram_blend) == histogram_blend {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_distance_estimation(&self, distance_estimation: DistanceEstimation) {
+        let previous = self.coloring();
+        if self.distance_estimation.replace(distance_estimation) == distance_estimation {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_boundary_width(&self, boundary_width: f64) {
+        if self.boundary_width.replace(boundary_width) == boundary_width {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_lighting(&self, lighting: bool) {
+        let previous = self.coloring();
+        if self.lighting.replace(lighting) == lighting {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_light_angle(&self, light_angle: f64) {
+        if self.light_angle.replace(light_angle) == light_angle {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_light_height(&self, light_height: f64) {
+        if self.light_height.replace(light_height) == light_height {
+            return;
+        }
+
+        self.recolor();
+    }
+
+    fn set_coloring_algorithm(&self, coloring_algorithm: ColoringAlgorithm) {
+        let previous = self.coloring();
+        if self.coloring_algorithm.replace(coloring_algorithm) == coloring_algorithm {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_stripe_density(&self, stripe_density: f64) {
+        let previous = self.coloring();
+        if self.stripe_density.replace(stripe_density) == stripe_density {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_trap_x(&self, trap_x: f64) {
+        let previous = self.coloring();
+        if self.trap_x.replace(trap_x) == trap_x {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_trap_y(&self, trap_y: f64) {
+        let previous = self.coloring();
+        if self.trap_y.replace(trap_y) == trap_y {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_trap_angle(&self, trap_angle: f64) {
+        let previous = self.coloring();
+        if self.trap_angle.replace(trap_angle) == trap_angle {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    fn set_interior_coloring(&self, interior_coloring: InteriorColoring) {
+        let previous = self.coloring();
+        if self.interior_coloring.replace(interior_coloring) == interior_coloring {
+            return;
+        }
+
+        self.coloring_changed(&previous);
+    }
+
+    /// Re-color or re-render the current view after a coloring parameter has changed that
+    /// might need additional data from iterating the orbits
+    ///
+    /// The derivative, orbit statistics, atom domains and cycles are only calculated while
+    /// rendering if the coloring uses them.
+    fn coloring_changed(&self, previous: &Coloring) {
+        if self.render_mode.get() != RenderMode::EscapeTime {
+            return;
+        }
+
+        if self.coloring().requires_render(previous) {
+            self.rerender();
+        } else {
+            self.recolor();
+        }
+    }
+
+    /// Re-render the current view after a rendering parameter has changed
+    fn rerender(&self) {
         let surface_size = self.surface_size.get();
+        if surface_size.0 != 0 && surface_size.1 != 0 {
+            self.trigger_render();
+        }
+    }
 
-        if self.move_controller.is_recognized() {
-            if let Some((x, y)) = self.move_controller.offset() {
-                let view = self.view.get();
-                rect.x -= x / surface_size.0 as f64 * view.width;
-                rect.y -= y / surface_size.1 as f64 * view.height;
-            }
+    /// Re-color the current view after a coloring parameter has changed
+    ///
+    /// Densities of escaping orbits don't use the coloring.
+    fn recolor(&self) {
+        let surface_size = self.surface_size.get();
+        if surface_size.0 == 0
+            || surface_size.1 == 0
+            || !self.obj().is_mapped()
+            || self.render_mode.get() != RenderMode::EscapeTime
+        {
+            return;
         }
 
+        let frame = self.frame.get() + 1;
+        self.frame.set(frame);
+
         self.command_sender
-            .send(Command::Render {
-                rect,
-                target_width: surface_size.0 * 2,
-                target_height: surface_size.1 * 2,
+            .send(Command::Recolor {
+                coloring: self.coloring(),
+                frame,
             })
             .unwrap();
     }
-}
 
-impl AsRef<[u8]> for Image {
-    fn as_ref(&self) -> &[u8] {
-        use zerocopy::IntoBytes;
-        self.pixels.as_bytes()
+    fn coloring(&self) -> Coloring {
+        Coloring {
+            palette: self.palette.borrow().clone(),
+            period: self.palette_period.get(),
+            offset: self.color_offset.get(),
+            reversed: self.palette_reversed.get(),
+            interpolation: self.interpolation.get(),
+            mapping: self.color_mapping.get(),
+            histogram_blend: self.histogram_blend.get(),
+            distance_estimation: self.distance_estimation.get(),
+            boundary_width: self.boundary_width.get(),
+            lighting: self.lighting.get(),
+            light_angle: self.light_angle.get(),
+            light_height: self.light_height.get(),
+            algorithm: self.coloring_algorithm.get(),
+            stripe_density: self.stripe_density.get(),
+            trap_center: num_complex::Complex64::new(self.trap_x.get(), self.trap_y.get()),
+            trap_angle: self.trap_angle.get(),
+            interior: self.interior_coloring.get(),
+        }
     }
-}
 
-impl Pixel {
-    fn new(r: u8, g: u8, b: u8) -> Self {
-        Pixel { a: 255, r, g, b }
-    }
-
-    fn interpolate(self, other: Self, frac: f64) -> Self {
-        Pixel::new(
-            f64::clamp(
-                self.r as f64 + (frac * (other.r as f64 - self.r as f64)),
-                0.0,
-                255.0,
-            ) as u8,
-            f64::clamp(
-                self.g as f64 + (frac * (other.g as f64 - self.g as f64)),
-                0.0,
-                255.0,
-            ) as u8,
-            f64::clamp(
-                self.b as f64 + (frac * (other.b as f64 - self.b as f64)),
-                0.0,
-                255.0,
-            ) as u8,
+    /// Fractal with all its current parameters
+    fn fractal(&self) -> Fractal {
+        Fractal::new(
+            self.fractal.get(),
+            self.exponent.get(),
+            self.phoenix_p.get(),
+            &self.formula.borrow(),
+            &self.polynomial.borrow(),
         )
     }
-}
 
-fn render_thread(commands: &mpsc::Receiver<Command>, surfaces: &async_channel::Sender<Image>) {
-    loop {
-        let mut command = commands.recv().unwrap();
+    fn on_render_done(&self, tile: Tile) {
+        if tile.frame < self.first_frame.get() {
+            return;
+        }
+
+        let position = (tile.x, tile.y);
 
-        // Get last command that was ever send, but always break on quit
-        while let Ok(cmd) = commands.try_recv() {
-            command = cmd;
-            if let Command::Quit = command {
-                break;
+        // Tiles of older frames or coarser passes might arrive after newer ones. Tiles of the
+        // same frame and pass are colored again after the frame is complete.
+        if let Some(previous) = self.tiles.borrow().get(&position) {
+            if previous.frame > tile.frame
+                || previous.frame == tile.frame && previous.scale < tile.scale
+            {
+                return;
             }
         }
 
-        match command {
-            Command::Quit => break,
-            Command::Render {
+        let max_iterations = tile.max_iterations;
+        let rect = graphene::Rect::new(
+            tile.x as f32 / 2.0,
+            tile.y as f32 / 2.0,
+            (tile.width * tile.scale) as f32 / 2.0,
+            (tile.height * tile.scale) as f32 / 2.0,
+        );
+        let (frame, scale) = (tile.frame, tile.scale);
+        let (width, height, stride) = (tile.width as i32, tile.height as i32, tile.width * 4);
+        let texture = gdk::MemoryTexture::new(
+            width,
+            height,
+            gdk::MemoryFormat::A8r8g8b8,
+            &glib::Bytes::from_owned(tile),
+            stride,
+        );
+
+        self.tiles.borrow_mut().insert(
+            position,
+            TileTexture {
+                texture,
                 rect,
-                target_width,
-                target_height,
-            } => {
-                let surface = create_image(rect, target_width, target_height);
-                surfaces.send_blocking(surface).unwrap();
+                frame,
+                scale,
+            },
+        );
+        self.obj().queue_draw();
+
+        if self.effective_max_iterations.replace(max_iterations) != max_iterations {
+            self.obj().notify_effective_max_iterations();
+        }
+    }
+
+    /// Translation of the view in fractions of its size for moving it by `x` and `y`
+    ///
+    /// This is rounded to whole pixels of the rendered image so that the renderer can reuse
+    /// the pixels of the previous frame.
+    fn move_translation(&self, x: f64, y: f64) -> (f64, f64) {
+        let surface_size = self.surface_size.get();
+        let (width, height) = (surface_size.0 * 2, surface_size.1 * 2);
+
+        (
+            (-x * 2.0).round() / (width - 1) as f64,
+            (-y * 2.0).round() / (height - 1) as f64,
+        )
+    }
+
+    /// Nothing is rendered while the widget is unmapped, it is rendered again once mapped
+    fn trigger_render(&self) {
+        if !self.obj().is_mapped() {
+            return;
+        }
+
+        let mut view = self.view.borrow().clone();
+        let surface_size = self.surface_size.get();
+
+        if self.move_controller.is_recognized() {
+            if let Some((x, y)) = self.move_controller.offset() {
+                let (dx, dy) = self.move_translation(x, y);
+                view.translate(dx, dy);
             }
         }
+
+        let cancel = CancellationToken::default();
+        self.render_cancellation.replace(cancel.clone()).cancel();
+        let frame = self.frame.get() + 1;
+        self.frame.set(frame);
+
+        let julia = self
+            .julia
+            .get()
+            .then(|| num_complex::Complex64::new(self.julia_x.get(), self.julia_y.get()));
+        let command = match self.render_mode.get() {
+            RenderMode::EscapeTime => Command::Render {
+                view,
+                target_width: surface_size.0 * 2,
+                target_height: surface_size.1 * 2,
+                max_iterations: self.max_iterations.get(),
+                iteration_mode: self.iteration_mode.get(),
+                subdivision: self.subdivision.get(),
+                fractal: self.fractal(),
+                julia,
+                coloring: self.coloring(),
+                frame,
+                cancel,
+            },
+            mode => Command::RenderDensity {
+                view,
+                target_width: surface_size.0 * 2,
+                target_height: surface_size.1 * 2,
+                max_iterations: self.max_iterations.get(),
+                mode,
+                fractal: self.fractal(),
+                julia,
+                frame,
+                cancel,
+            },
+        };
+        self.command_sender.send(command).unwrap();
     }
 }
 
@@ -561,84 +1122,3 @@ fn calculate_selection_rectangle(rect: Rectangle, surface_size: (usize, usize))
         ..rect
     }
 }
-
-static COLORS: Lazy<[Pixel; 360]> = Lazy::new(|| {
-    let mut colors = [Default::default(); 360];
-
-    let s = 1.0;
-    let v = 1.0;
-    for (h, color) in colors.iter_mut().enumerate() {
-        let c = v * s;
-        let x = c * (1.0 - f64::abs(((h as f64) / 60.0) % 2.0 - 1.0));
-        let m = v - c;
-
-        let (r, g, b) = if h < 60 {
-            (c, x, 0.0)
-        } else if h < 120 {
-            (x, c, 0.0)
-        } else if h < 180 {
-            (0.0, c, x)
-        } else if h < 240 {
-            (0.0, x, c)
-        } else if h < 300 {
-            (x, 0.0, c)
-        } else {
-            (c, 0.0, x)
-        };
-
-        *color = Pixel::new(
-            ((r + m) * 255.0) as u8,
-            ((g + m) * 255.0) as u8,
-            ((b + m) * 255.0) as u8,
-        );
-    }
-
-    colors
-});
-
-fn create_image(rect: Rectangle, target_width: usize, target_height: usize) -> Image {
-    let (xscale, yscale) = (
-        rect.width / (target_width as f64 - 1.0),
-        rect.height / (target_height as f64 - 1.0),
-    );
-
-    let pixels = (0..target_height)
-        .into_par_iter()
-        .flat_map(|target_y| rayon::iter::repeat_n(target_y, target_width).enumerate())
-        .map(|(target_x, target_y)| {
-            let c = Complex64::new(
-                rect.x + target_x as f64 * xscale,
-                rect.y + target_y as f64 * yscale,
-            );
-
-            let mut z = Complex64::new(0.0, 0.0);
-            let mut it = 0;
-            let max_it = 1000;
-
-            while z.norm_sqr() < ((1 << 16) as f64) && it < max_it {
-                z = z * z + c;
-                it += 1;
-            }
-
-            if it < max_it {
-                let log_zn = f64::ln(z.norm_sqr()) / 2.0;
-                let nu = f64::ln(log_zn / f64::ln(2.0)) / f64::ln(2.0);
-
-                let it = it as f64 + 1.0 - nu;
-                let c1 = COLORS[it.floor() as usize % 360];
-                let c2 = COLORS[(it.floor() + 1.0) as usize % 360];
-                Pixel::interpolate(c1, c2, it.fract())
-            } else {
-                Pixel::default()
-            }
-        })
-        .collect::<Vec<_>>();
-
-    assert_eq!(pixels.len(), target_width * target_height);
-
-    Image {
-        pixels,
-        width: target_width,
-        height: target_height,
-    }
-}
@@ -2,6 +2,11 @@ use gtk::glib;
 
 mod imp;
 
+pub use crate::render::{
+    ColorMapping, ColoringAlgorithm, DistanceEstimation, Formula, FormulaPart, FractalFamily,
+    Palette, Polynomial, PolynomialInput, MAX_MAX_ITERATIONS,
+};
+
 glib::wrapper! {
     pub struct Widget(ObjectSubclass<imp::Widget>) @extends gtk::Widget, @implements gtk::Buildable, gtk::ConstraintTarget, gtk::Accessible;
 }